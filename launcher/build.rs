@@ -9,9 +9,15 @@ fn main() {
     let flags = vergen::ConstantsFlags::all();
     vergen::generate_cargo_keys(flags).unwrap();
 
-    println!("cargo:rustc-link-search=native=lib");
-    println!("cargo:rustc-link-lib=static=physfs");
-    println!("cargo:rustc-link-lib=dylib=rts");
+    // The bundled PhysFS and rts libraries are only available for Windows,
+    // so elsewhere we link against the system PhysFS library
+    if env::var("CARGO_CFG_TARGET_OS").unwrap() == "windows" {
+        println!("cargo:rustc-link-search=native=lib");
+        println!("cargo:rustc-link-lib=static=physfs");
+        println!("cargo:rustc-link-lib=dylib=rts");
+    } else {
+        println!("cargo:rustc-link-lib=dylib=physfs");
+    }
     println!("cargo:rerun-if-changed=physfs.h");
 
     let bindings = bindgen::Builder::default()
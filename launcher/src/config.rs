@@ -0,0 +1,504 @@
+use crate::jmx;
+use crate::preset::Preset;
+use crate::time::UtcDateTime;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ArgMatches;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = "openil2.toml";
+
+/// Extra JVM options separated by whitespace, applied after those from the config file.
+/// They are quoted in the same way as `JAVA_TOOL_OPTIONS`, see `split_java_opts`.
+pub const JAVA_OPTS_ENV: &str = "OPENIL2_JAVA_OPTS";
+
+pub const DEFAULT_MAIN_CLASS: &str = "com.maddox.il2.game.GameWin3D";
+pub const DEFAULT_SERVER_MAIN_CLASS: &str = "com.maddox.il2.game.DServer";
+pub const DEFAULT_HEAP_SIZE: &str = "512m";
+pub const DEFAULT_DEBUG_PORT: u16 = 5005;
+pub const DEFAULT_JMX_PORT: u16 = 9010;
+pub const DEFAULT_JFR_SETTINGS: &str = "default";
+
+/// The directory that Java agents are loaded from
+pub const JAVA_AGENT_DIR: &str = "mods";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub main_class: Option<String>,
+    /// Start the dedicated server instead of the game
+    pub server: bool,
+    /// Attach to the console that the launcher was started from, or open a
+    /// new one, so that game output is visible in release builds
+    pub console: bool,
+    /// The profile to use when none is given on the command line
+    pub profile: Option<String>,
+    /// Archives or directories to add to the search path ahead of files.zip
+    pub mounts: Vec<PathBuf>,
+    /// The directory that the game writes to, instead of the game directory
+    pub write_dir: Option<PathBuf>,
+    /// Write to a directory of the current user's for the selected profile,
+    /// instead of the game directory, unless `write-dir` is set
+    pub user_write_dir: bool,
+    /// Allow more than one instance of the game, e.g. for dedicated servers
+    /// which each use their own profile
+    pub allow_multiple: bool,
+    /// Arguments passed to the main method of the game
+    pub game_args: Vec<String>,
+    pub jvm: JvmConfig,
+    pub debug: DebugConfig,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// A named set of mounts and JVM settings, e.g. for a particular mod pack
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profile {
+    pub mounts: Vec<PathBuf>,
+    pub class_path: Vec<String>,
+    pub jvm_options: Vec<String>,
+    pub preset: Option<Preset>,
+    pub write_dir: Option<PathBuf>,
+    pub user_write_dir: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct JvmConfig {
+    /// The home directory of the Java runtime to use instead of the bundled runtime
+    pub java_home: Option<PathBuf>,
+    pub heap_size: Option<String>,
+    pub preset: Option<Preset>,
+    /// Extra options passed verbatim to the JVM, e.g. `-Dname=value` or `-XX:+Flag`
+    pub options: Vec<String>,
+    /// Extra entries appended to the Java classpath
+    pub class_path: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct DebugConfig {
+    pub await_debug: bool,
+    pub debug_port: Option<u16>,
+    pub jmx_monitoring: bool,
+    pub jmx_port: Option<u16>,
+    /// The address that JMX listens on, `127.0.0.1` by default
+    pub jmx_bind_address: Option<String>,
+    /// Enables JMX authentication with the users and passwords in the file
+    pub jmx_password_file: Option<PathBuf>,
+    /// The roles of the users in the password file
+    pub jmx_access_file: Option<PathBuf>,
+    /// Generate a password file and access file for a read only user,
+    /// unless the password file already exists
+    pub jmx_generate_credentials: bool,
+    /// Enables SSL for JMX with the keystore at this path
+    pub jmx_keystore: Option<PathBuf>,
+    pub jmx_keystore_password: Option<String>,
+    pub gc_logging: bool,
+    /// Start a Flight Recording with the given settings, `default`, `profile`
+    /// or the path of a `.jfc` file
+    pub jfr: Option<String>,
+    pub native_memory_tracking: Option<NativeMemoryTracking>,
+    /// Java agents to attach from the mods directory, as `file.jar` or `file.jar=options`
+    pub java_agents: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NativeMemoryTracking {
+    Summary,
+    Detail,
+}
+
+impl NativeMemoryTracking {
+    pub fn from_name(name: &str) -> Option<NativeMemoryTracking> {
+        match name {
+            "summary" => Some(NativeMemoryTracking::Summary),
+            "detail" => Some(NativeMemoryTracking::Detail),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NativeMemoryTracking::Summary => "summary",
+            NativeMemoryTracking::Detail => "detail",
+        }
+    }
+}
+
+/// The `-javaagent` option for an agent in the mods directory
+fn java_agent_option(agent: &str) -> Result<String> {
+    let (jar_name, agent_options) = match agent.find('=') {
+        Some(idx) => (&agent[..idx], Some(&agent[idx..])),
+        None => (agent, None),
+    };
+
+    let jar_path = Path::new(jar_name);
+
+    // Only allow agents from the mods directory itself
+    if jar_path.components().count() != 1 || jar_path.file_name().is_none() {
+        bail!(
+            "The Java agent {} must be the name of a file in the {} directory",
+            jar_name,
+            JAVA_AGENT_DIR
+        );
+    }
+
+    let agent_path = Path::new(JAVA_AGENT_DIR).join(jar_path);
+
+    if !agent_path.is_file() {
+        bail!("Unable to find Java agent {}", agent_path.display());
+    }
+
+    Ok(format!(
+        "-javaagent:{}/{}{}",
+        JAVA_AGENT_DIR,
+        jar_name,
+        agent_options.unwrap_or("")
+    ))
+}
+
+fn parse_port(cli_args: &ArgMatches, name: &str) -> Result<Option<u16>> {
+    cli_args
+        .value_of(name)
+        .map(|port| {
+            port.parse::<u16>()
+                .with_context(|| format!("Invalid value {} for --{}", port, name))
+        })
+        .transpose()
+}
+
+/// Split JVM options separated by whitespace in the same way as the JVM splits
+/// `JAVA_TOOL_OPTIONS`: single or double quotes group text that contains
+/// whitespace, e.g. `"-Dpath=C:\Program Files\IL-2"` or `-Dpath='C:\Program Files'`,
+/// and are removed. There is no escape character.
+fn split_java_opts(java_opts: &str) -> Result<Vec<String>> {
+    let mut opts = Vec::new();
+    let mut chars = java_opts.chars().peekable();
+
+    loop {
+        while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+
+        if chars.peek().is_none() {
+            return Ok(opts);
+        }
+
+        let mut opt = String::new();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' | '\'' => loop {
+                    match chars.next() {
+                        Some(quoted) if quoted == c => break,
+                        Some(quoted) => opt.push(quoted),
+                        None => bail!("Unmatched quote in {}", java_opts),
+                    }
+                },
+                c if c.is_whitespace() => break,
+                c => opt.push(c),
+            }
+        }
+
+        opts.push(opt);
+    }
+}
+
+impl Config {
+    /// Read the launcher configuration, falling back to the defaults
+    /// when the file doesn't exist
+    pub fn load(path: &Path) -> Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let config_str = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+
+        toml::from_str(&config_str).with_context(|| format!("Unable to parse {}", path.display()))
+    }
+
+    /// Add the settings of the named profile to the top level settings
+    pub fn select_profile(&mut self, name: &str) -> Result<()> {
+        let profile = self.profiles.get(name).cloned().ok_or_else(|| {
+            let known: Vec<&str> = self.profiles.keys().map(|k| k.as_str()).collect();
+            anyhow!(
+                "Unknown profile {}, the profiles in {} are: {}",
+                name,
+                CONFIG_FILE,
+                known.join(", ")
+            )
+        })?;
+
+        self.profile = Some(name.to_string());
+        self.mounts.extend(profile.mounts);
+        self.jvm.class_path.extend(profile.class_path);
+        self.jvm.options.extend(profile.jvm_options);
+
+        if profile.preset.is_some() {
+            self.jvm.preset = profile.preset;
+        }
+
+        if profile.write_dir.is_some() {
+            self.write_dir = profile.write_dir;
+        }
+
+        if profile.user_write_dir {
+            self.user_write_dir = true;
+        }
+
+        Ok(())
+    }
+
+    /// Override the values from the configuration file with the flags
+    /// given on the command line and the JVM options from the environment
+    pub fn apply_cli_args(&mut self, cli_args: &ArgMatches) -> Result<()> {
+        if let Some(profile) = cli_args
+            .value_of("profile")
+            .map(|profile| profile.to_string())
+            .or_else(|| self.profile.clone())
+        {
+            self.select_profile(&profile)?;
+        }
+
+        if let Some(java_opts) = std::env::var_os(JAVA_OPTS_ENV) {
+            let java_opts = java_opts
+                .into_string()
+                .map_err(|_| anyhow!("{} is not a valid UTF-8 string", JAVA_OPTS_ENV))?;
+
+            let java_opts = split_java_opts(&java_opts)
+                .with_context(|| format!("Unable to read the JVM options in {}", JAVA_OPTS_ENV))?;
+
+            self.jvm.options.extend(java_opts);
+        }
+
+        if let Some(jvm_opts) = cli_args.values_of("jvm-opt") {
+            self.jvm.options.extend(jvm_opts.map(|opt| opt.to_string()));
+        }
+
+        if let Some(main_class) = cli_args.value_of("main-class") {
+            self.main_class = Some(main_class.to_string());
+        }
+
+        if cli_args.is_present("server") {
+            self.server = true;
+        }
+
+        if cli_args.is_present("console") {
+            self.console = true;
+        }
+
+        if let Some(write_dir) = cli_args.value_of("write-dir") {
+            self.write_dir = Some(PathBuf::from(write_dir));
+        }
+
+        if cli_args.is_present("user-write-dir") {
+            self.user_write_dir = true;
+        }
+
+        if cli_args.is_present("allow-multiple") {
+            self.allow_multiple = true;
+        }
+
+        if let Some(game_args) = cli_args.values_of("game-args") {
+            self.game_args.extend(game_args.map(|arg| arg.to_string()));
+        }
+
+        if let Some(preset) = cli_args.value_of("preset") {
+            self.jvm.preset = Some(
+                Preset::from_name(preset).ok_or_else(|| anyhow!("Unknown preset {}", preset))?,
+            );
+        }
+
+        if let Some(heap_size) = cli_args.value_of("heap-size") {
+            self.jvm.heap_size = Some(heap_size.to_string());
+        }
+
+        if cli_args.is_present("await-debug") {
+            self.debug.await_debug = true;
+        }
+
+        if let Some(debug_port) = parse_port(cli_args, "debug-port")? {
+            self.debug.debug_port = Some(debug_port);
+        }
+
+        if cli_args.is_present("jmx-monitoring") {
+            self.debug.jmx_monitoring = true;
+        }
+
+        if let Some(jmx_port) = parse_port(cli_args, "jmx-port")? {
+            self.debug.jmx_port = Some(jmx_port);
+        }
+
+        if let Some(bind_address) = cli_args.value_of("jmx-bind-address") {
+            self.debug.jmx_bind_address = Some(bind_address.to_string());
+        }
+
+        if let Some(password_file) = cli_args.value_of("jmx-password-file") {
+            self.debug.jmx_password_file = Some(PathBuf::from(password_file));
+        }
+
+        if let Some(access_file) = cli_args.value_of("jmx-access-file") {
+            self.debug.jmx_access_file = Some(PathBuf::from(access_file));
+        }
+
+        if cli_args.is_present("jmx-generate-credentials") {
+            self.debug.jmx_generate_credentials = true;
+        }
+
+        if let Some(keystore) = cli_args.value_of("jmx-keystore") {
+            self.debug.jmx_keystore = Some(PathBuf::from(keystore));
+        }
+
+        if cli_args.is_present("gc-logging") {
+            self.debug.gc_logging = true;
+        }
+
+        if cli_args.is_present("jfr") {
+            self.debug.jfr = Some(
+                cli_args
+                    .value_of("jfr")
+                    .unwrap_or(DEFAULT_JFR_SETTINGS)
+                    .to_string(),
+            );
+        }
+
+        if cli_args.is_present("native-memory-tracking") {
+            let mode = cli_args
+                .value_of("native-memory-tracking")
+                .unwrap_or("summary");
+
+            self.debug.native_memory_tracking = Some(
+                NativeMemoryTracking::from_name(mode)
+                    .ok_or_else(|| anyhow!("Unknown native memory tracking mode {}", mode))?,
+            );
+        }
+
+        if let Some(java_agents) = cli_args.values_of("java-agent") {
+            self.debug
+                .java_agents
+                .extend(java_agents.map(|agent| agent.to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub fn main_class(&self) -> &str {
+        match self.main_class.as_deref() {
+            Some(main_class) => main_class,
+            None if self.server => DEFAULT_SERVER_MAIN_CLASS,
+            None => DEFAULT_MAIN_CLASS,
+        }
+    }
+
+    /// Whether the launcher should run with a console, which the dedicated
+    /// server always needs for its logs and commands
+    pub fn use_console(&self) -> bool {
+        self.console || self.server
+    }
+
+    pub fn class_path(&self, default_class_path: &[&str]) -> Vec<String> {
+        default_class_path
+            .iter()
+            .map(|entry| entry.to_string())
+            .chain(self.jvm.class_path.iter().cloned())
+            .collect()
+    }
+
+    pub fn heap_size(&self) -> &str {
+        self.jvm.heap_size.as_deref().unwrap_or(DEFAULT_HEAP_SIZE)
+    }
+
+    /// The JVM options for the given preset and the configured heap size,
+    /// debugging and profiling settings, excluding the classpath
+    pub fn jvm_options(&self, preset: Preset) -> Result<Vec<String>> {
+        let mut options = vec!["-Djava.locale.providers=COMPAT".to_string()];
+
+        options.extend(preset.jvm_options(self.heap_size()));
+
+        if self.debug.await_debug {
+            let debug_port = self.debug.debug_port.unwrap_or(DEFAULT_DEBUG_PORT);
+            options.push(format!(
+                "-agentlib:jdwp=transport=dt_socket,server=y,suspend=y,address=127.0.0.1:{}",
+                debug_port
+            ));
+        }
+
+        if self.debug.jmx_monitoring {
+            options.extend(jmx::jmx_options(&self.debug)?);
+        }
+
+        if self.debug.gc_logging {
+            options.push("-Xlog:gc*,age*=debug:file=gc.log::filecount=1,filesize=5M".to_string());
+        }
+
+        if let Some(jfr_settings) = self.debug.jfr.as_ref() {
+            // The JVM splits the recording's parameters at commas
+            if jfr_settings.contains(',') {
+                bail!(
+                    "The Flight Recorder settings {} can't contain a comma, use a .jfc file with a path that doesn't",
+                    jfr_settings
+                );
+            }
+
+            options.push(format!(
+                "-XX:StartFlightRecording=settings={},filename=openil2-{}.jfr,dumponexit=true",
+                jfr_settings,
+                UtcDateTime::now().file_timestamp()
+            ));
+        }
+
+        if let Some(mode) = self.debug.native_memory_tracking {
+            options.push(format!("-XX:NativeMemoryTracking={}", mode.name()));
+        }
+
+        for agent in self.debug.java_agents.iter() {
+            options.push(java_agent_option(agent)?);
+        }
+
+        options.extend(self.jvm.options.iter().cloned());
+
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(java_opts: &str) -> Vec<String> {
+        split_java_opts(java_opts).unwrap()
+    }
+
+    #[test]
+    fn splits_empty_java_opts() {
+        assert!(split("").is_empty());
+        assert!(split(" \t\n ").is_empty());
+    }
+
+    #[test]
+    fn splits_java_opts_on_runs_of_whitespace() {
+        assert_eq!(
+            split("  -Xmx2g \t -XX:+UseG1GC\n-Dfoo=bar  "),
+            ["-Xmx2g", "-XX:+UseG1GC", "-Dfoo=bar"]
+        );
+    }
+
+    #[test]
+    fn keeps_quoted_spaces_in_java_opts() {
+        assert_eq!(
+            split(r#"-Dname="Bf 109" '-Dpath=C:\Program Files\IL-2' -Dempty="""#),
+            ["-Dname=Bf 109", r"-Dpath=C:\Program Files\IL-2", "-Dempty="]
+        );
+        assert_eq!(split(r#"-Dquote="it's""#), ["-Dquote=it's"]);
+    }
+
+    #[test]
+    fn rejects_unterminated_quote_in_java_opts() {
+        assert!(split_java_opts(r#"-Xmx2g -Dname="Bf 109"#).is_err());
+        assert!(split_java_opts("-Dname='Bf").is_err());
+    }
+}
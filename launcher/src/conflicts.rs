@@ -0,0 +1,331 @@
+use crate::physfs::{self, FileType};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A file which is supplied by more than one entry on the search path
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Conflict {
+    /// The path of the file as it is requested by the game
+    pub path: String,
+    /// The class defined by the file, if it is a class file
+    pub class_name: Option<String>,
+    /// The archives and directories which supply the file, in search path order
+    pub providers: Vec<String>,
+    /// The provider that PhysFS reads the file from
+    pub used: Option<String>,
+}
+
+/// A file stored under a name that the game never requests, as the game
+/// normalises every path before looking it up
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UnreachableFile {
+    pub path: String,
+    pub providers: Vec<String>,
+}
+
+/// One of the names in a case-only collision and the archives which store it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CollidingName {
+    pub name: String,
+    pub providers: Vec<String>,
+    /// Whether the game can read the file under this name
+    pub reachable: bool,
+}
+
+/// Files whose names differ only in case, and so refer to the same file
+/// once the game normalises them
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CaseCollision {
+    /// The normalised path requested by the game
+    pub path: String,
+    pub names: Vec<CollidingName>,
+    /// The provider that PhysFS reads the normalised path from
+    pub used: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConflictReport {
+    /// The archives and directories on the search path, in search order
+    pub archives: Vec<String>,
+    pub file_count: usize,
+    pub conflicts: Vec<Conflict>,
+    pub case_collisions: Vec<CaseCollision>,
+    pub unreachable: Vec<UnreachableFile>,
+}
+
+/// Add the files below a directory on the search path to `paths`, as PhysFS stores them
+fn list_files(dir: &str, paths: &mut Vec<String>) -> Result<()> {
+    for name in physfs::list_dir_exact(dir)? {
+        let path = if dir.is_empty() {
+            name
+        } else {
+            format!("{}/{}", dir, name)
+        };
+
+        match physfs::stat_exact(&path)? {
+            Some(stat) if stat.file_type == FileType::Directory => list_files(&path, paths)?,
+            _ => paths.push(path),
+        }
+    }
+
+    Ok(())
+}
+
+/// A file as it is stored in an archive or directory
+struct ListedFile {
+    path: String,
+    /// Whether the game's normalised path finds the file, which depends on
+    /// whether the archive or directory is searched ignoring case
+    reachable: bool,
+}
+
+/// List the files in an archive or directory mounted on its own
+fn list_mounted_archive(archive: &str) -> Result<Vec<ListedFile>> {
+    let mut paths = Vec::new();
+
+    list_files("", &mut paths)
+        .with_context(|| format!("Unable to list the files in {}", archive))?;
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let reachable = path == physfs::normalise_path(&path) || physfs::exists(&path)?;
+            Ok(ListedFile { path, reachable })
+        })
+        .collect()
+}
+
+/// List the files in each archive and directory on the search path separately,
+/// by mounting it on its own, and then restore the search path
+fn list_archives(mounts: &[(String, String)]) -> Result<Vec<Vec<ListedFile>>> {
+    for (archive, _) in mounts.iter() {
+        physfs::unmount(archive)?;
+    }
+
+    let listings = mounts
+        .iter()
+        .map(|(archive, mount_point)| {
+            physfs::mount_at(archive, Some(mount_point), true)?;
+
+            let listed = list_mounted_archive(archive);
+
+            physfs::unmount(archive)?;
+
+            listed
+        })
+        .collect();
+
+    for (archive, mount_point) in mounts.iter() {
+        physfs::mount_at(archive, Some(mount_point), true)?;
+    }
+
+    listings
+}
+
+fn class_name(path: &str) -> Option<String> {
+    path.strip_suffix(".class")
+        .map(|class_path| class_path.replace("/", "."))
+}
+
+/// Enumerate the files in every archive and directory on the PhysFS search path,
+/// finding the files that are supplied by more than one of them and those that
+/// the game can't read
+pub fn find_conflicts() -> Result<ConflictReport> {
+    let archives = physfs::search_path();
+
+    let mounts: Vec<(String, String)> = archives
+        .iter()
+        .map(|archive| {
+            let mount_point = physfs::mount_point(archive).unwrap_or_else(|| "/".to_string());
+            (archive.clone(), mount_point)
+        })
+        .collect();
+
+    // Names are exact, as PhysFS only finds a file in an archive by its exact name
+    let mut providers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut unreachable_in: BTreeSet<(String, String)> = BTreeSet::new();
+
+    for (archive, files) in archives.iter().zip(list_archives(&mounts)?) {
+        for file in files {
+            if !file.reachable {
+                unreachable_in.insert((file.path.clone(), archive.clone()));
+            }
+
+            providers
+                .entry(file.path)
+                .or_default()
+                .push(archive.clone());
+        }
+    }
+
+    let mut names_by_path: BTreeMap<String, Vec<&String>> = BTreeMap::new();
+
+    for name in providers.keys() {
+        names_by_path
+            .entry(physfs::normalise_path(name))
+            .or_default()
+            .push(name);
+    }
+
+    let is_reachable = |name: &String, archive: &String| {
+        !unreachable_in.contains(&(name.clone(), archive.clone()))
+    };
+
+    let mut case_collisions = Vec::new();
+    let mut colliding_names = BTreeSet::new();
+
+    for (path, names) in names_by_path.iter().filter(|(_, names)| names.len() > 1) {
+        colliding_names.extend(names.iter().cloned());
+
+        case_collisions.push(CaseCollision {
+            path: path.clone(),
+            names: names
+                .iter()
+                .map(|name| CollidingName {
+                    name: (*name).clone(),
+                    providers: providers[*name].clone(),
+                    reachable: providers[*name]
+                        .iter()
+                        .any(|archive| is_reachable(name, archive)),
+                })
+                .collect(),
+            used: physfs::real_dir(path)?,
+        });
+    }
+
+    let mut conflicts = Vec::new();
+    let mut unreachable = Vec::new();
+
+    // Each collision is reported once, with all of its names
+    for (name, archives) in providers
+        .iter()
+        .filter(|(name, _)| !colliding_names.contains(name))
+    {
+        if !archives.iter().any(|archive| is_reachable(name, archive)) {
+            unreachable.push(UnreachableFile {
+                path: name.clone(),
+                providers: archives.clone(),
+            });
+        } else if archives.len() > 1 {
+            conflicts.push(Conflict {
+                path: name.clone(),
+                class_name: class_name(name),
+                providers: archives.clone(),
+                used: physfs::real_dir_exact(name)?,
+            });
+        }
+    }
+
+    Ok(ConflictReport {
+        archives,
+        file_count: providers.len(),
+        conflicts,
+        case_collisions,
+        unreachable,
+    })
+}
+
+fn provider_status(provider: &str, used: Option<&String>) -> &'static str {
+    if used.map(|used| used == provider).unwrap_or(false) {
+        "used"
+    } else {
+        "hidden"
+    }
+}
+
+fn print_conflicts<'a, I>(heading: &str, conflicts: I)
+where
+    I: Iterator<Item = &'a Conflict>,
+{
+    let conflicts: Vec<&Conflict> = conflicts.collect();
+
+    println!("{} ({}):", heading, conflicts.len());
+
+    for conflict in conflicts {
+        println!(
+            "  {}",
+            conflict.class_name.as_ref().unwrap_or(&conflict.path)
+        );
+
+        for provider in conflict.providers.iter() {
+            let status = provider_status(provider, conflict.used.as_ref());
+            println!("    [{}] {}", status, provider);
+        }
+    }
+}
+
+impl ConflictReport {
+    pub fn print_text(&self) {
+        println!("Search path:");
+
+        for (index, archive) in self.archives.iter().enumerate() {
+            println!("{:>5}. {}", index + 1, archive);
+        }
+
+        println!(
+            "{} files, {} supplied more than once",
+            self.file_count,
+            self.conflicts.len()
+        );
+
+        print_conflicts(
+            "Overridden files",
+            self.conflicts
+                .iter()
+                .filter(|conflict| conflict.class_name.is_none()),
+        );
+
+        print_conflicts(
+            "Class overrides",
+            self.conflicts
+                .iter()
+                .filter(|conflict| conflict.class_name.is_some()),
+        );
+
+        println!("Case-only collisions ({}):", self.case_collisions.len());
+
+        for collision in self.case_collisions.iter() {
+            println!("  {}", collision.path);
+
+            for name in collision.names.iter() {
+                for provider in name.providers.iter() {
+                    let status = if !name.reachable {
+                        "unreachable"
+                    } else {
+                        provider_status(provider, collision.used.as_ref())
+                    };
+
+                    println!("    [{}] {}: {}", status, provider, name.name);
+                }
+            }
+        }
+
+        println!(
+            "Unreachable files, whose names the game never requests ({}):",
+            self.unreachable.len()
+        );
+
+        for file in self.unreachable.iter() {
+            println!("  {}", file.path);
+
+            for provider in file.providers.iter() {
+                println!("    {}", provider);
+            }
+        }
+    }
+
+    pub fn print_json(&self) -> Result<()> {
+        let json =
+            serde_json::to_string_pretty(self).context("Unable to write the report as JSON")?;
+
+        println!("{}", json);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+#[cfg(windows)]
+use winapi::um::{consoleapi, wincon, winuser};
+
+/// Attach to the console of the parent process, e.g. a command prompt, so that
+/// release builds, which have no console of their own, can print to it.
+///
+/// Returns `false` if there is no parent console to attach to.
+#[cfg(windows)]
+pub fn attach_parent_console() -> bool {
+    // This also fails if we already have a console, e.g. in debug builds
+    unsafe { wincon::AttachConsole(wincon::ATTACH_PARENT_PROCESS) != 0 }
+}
+
+/// Attach to the console of the parent process, or open a new console window
+/// if the launcher wasn't started from one.
+///
+/// Returns `true` if a new console window was opened, which closes as soon as
+/// the launcher exits.
+#[cfg(windows)]
+pub fn attach_or_alloc_console() -> bool {
+    if attach_parent_console() {
+        false
+    } else {
+        unsafe { consoleapi::AllocConsole() != 0 }
+    }
+}
+
+/// Whether the launcher has a console to print to
+#[cfg(windows)]
+pub fn has_console() -> bool {
+    unsafe { !wincon::GetConsoleWindow().is_null() }
+}
+
+#[cfg(windows)]
+fn wide_string(string: &str) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    std::ffi::OsStr::new(string)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+/// Show an error in a message box, for when there is no console to print it to
+#[cfg(windows)]
+pub fn show_error_message(title: &str, message: &str) {
+    let title = wide_string(title);
+    let message = wide_string(message);
+
+    unsafe {
+        winuser::MessageBoxW(
+            std::ptr::null_mut(),
+            message.as_ptr(),
+            title.as_ptr(),
+            winuser::MB_OK | winuser::MB_ICONERROR,
+        );
+    }
+}
+
+#[cfg(not(windows))]
+pub fn attach_parent_console() -> bool {
+    true
+}
+
+#[cfg(not(windows))]
+pub fn attach_or_alloc_console() -> bool {
+    false
+}
+
+#[cfg(not(windows))]
+pub fn has_console() -> bool {
+    true
+}
+
+#[cfg(not(windows))]
+pub fn show_error_message(_title: &str, _message: &str) {}
+
+/// Keep a console window that the launcher opened until the user has read it
+pub fn wait_for_enter() {
+    println!();
+    println!("Press Enter to close this window");
+
+    let _ = std::io::stdin().read_line(&mut String::new());
+}
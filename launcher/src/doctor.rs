@@ -0,0 +1,186 @@
+use crate::config::Config;
+use crate::jvm;
+use crate::modload::{self, MODLOAD_FILE};
+use crate::physfs;
+use crate::search_path;
+use crate::{FILES_ZIP, PHYSFS_JAR};
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+const BOOTSTRAP_CLASSES: [&str; 4] = [
+    "com.maddox.rts.PhysFS",
+    "com.maddox.rts.PhysFSInputStream",
+    "com.maddox.rts.PhysFSLoader",
+    "com.maddox.rts.PhysFSReader",
+];
+
+const NATIVE_LIBRARIES: [&str; 3] = ["physfs_jni", "physfs_rts", "rts"];
+
+struct Doctor {
+    checks: usize,
+    failures: usize,
+}
+
+impl Doctor {
+    fn check<F>(&mut self, description: &str, check: F)
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        self.checks += 1;
+
+        match check() {
+            Ok(()) => println!("[PASS] {}", description),
+            Err(err) => {
+                self.failures += 1;
+                println!("[FAIL] {}: {:#}", description, err);
+            }
+        }
+    }
+}
+
+fn class_file_name(class_name: &str) -> String {
+    format!("{}.class", class_name.replace(".", "/"))
+}
+
+fn check_file(path: &Path) -> Result<()> {
+    if path.is_file() {
+        Ok(())
+    } else {
+        bail!("{} is missing", path.display())
+    }
+}
+
+fn check_zip_entries(path: &Path, entry_names: &[String]) -> Result<()> {
+    let zip_file =
+        File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+
+    let mut zip_archive = zip::ZipArchive::new(zip_file)
+        .with_context(|| format!("Unable to read zip file {}", path.display()))?;
+
+    for entry_name in entry_names {
+        zip_archive
+            .by_name(entry_name)
+            .with_context(|| format!("Couldn't find entry {}", entry_name))?;
+    }
+
+    Ok(())
+}
+
+/// The SFS files in the game directory, which must each have a repacked zip
+fn sfs_files() -> Result<Vec<PathBuf>> {
+    let mut sfs_files = Vec::new();
+
+    for entry in std::fs::read_dir(".").context("Unable to list the game directory")? {
+        let path = entry.context("Unable to list the game directory")?.path();
+
+        let is_sfs = path
+            .extension()
+            .map(|ext| ext.eq_ignore_ascii_case("sfs"))
+            .unwrap_or(false);
+
+        if is_sfs && path.is_file() {
+            sfs_files.push(path);
+        }
+    }
+
+    sfs_files.sort();
+
+    Ok(sfs_files)
+}
+
+fn check_class_resolves(class_name: &str) -> Result<()> {
+    if physfs::exists(&class_file_name(class_name))? {
+        Ok(())
+    } else {
+        bail!("Not found on the PhysFS search path")
+    }
+}
+
+/// Check the installation for the problems that most often stop the game
+/// from starting, without creating the JVM.
+///
+/// Returns `true` if every check passed.
+pub fn run(config: &Config) -> bool {
+    let mut doctor = Doctor {
+        checks: 0,
+        failures: 0,
+    };
+
+    match jvm::discover(config) {
+        Ok(java_runtime) => doctor.check(&format!("Java runtime {}", java_runtime), || {
+            java_runtime.validate()
+        }),
+        Err(err) => doctor.check("Java runtime", || Err(err)),
+    }
+
+    doctor.check(&format!("Bootstrap classes in {}", PHYSFS_JAR), || {
+        let entry_names: Vec<String> = BOOTSTRAP_CLASSES
+            .iter()
+            .map(|class_name| class_file_name(class_name))
+            .collect();
+
+        check_zip_entries(Path::new(PHYSFS_JAR), &entry_names)
+    });
+
+    doctor.check(&format!("Game archive {}", FILES_ZIP), || {
+        check_zip_entries(Path::new(FILES_ZIP), &[])
+    });
+
+    for library in NATIVE_LIBRARIES.iter() {
+        let file_name = format!("{}{}{}", DLL_PREFIX, library, DLL_SUFFIX);
+
+        doctor.check(&format!("Native library {}", file_name), || {
+            check_file(Path::new(&file_name))
+        });
+    }
+
+    match sfs_files() {
+        Ok(sfs_files) => {
+            for sfs_file in sfs_files {
+                let zip_file = sfs_file.with_extension("zip");
+
+                doctor.check(&format!("Repacked zip for {}", sfs_file.display()), || {
+                    check_zip_entries(&zip_file, &[])
+                });
+            }
+        }
+        Err(err) => doctor.check("SFS files in the game directory", || Err(err)),
+    }
+
+    doctor.check(&format!("{} entries", MODLOAD_FILE), || {
+        let entries = modload::read_modload(Path::new(MODLOAD_FILE))?;
+        let game_dir = env::current_dir().context("Unable to get current directory")?;
+        let problems: Vec<String> = modload::validate(&entries, &game_dir)
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect();
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            bail!("{}", problems.join("; "))
+        }
+    });
+
+    match search_path::init(config, None) {
+        Ok(_physfs) => {
+            for class_name in [config.main_class(), "com.maddox.rts.SFSInputStream"].iter() {
+                doctor.check(&format!("Class {}", class_name), || {
+                    check_class_resolves(class_name)
+                });
+            }
+        }
+        Err(err) => doctor.check("PhysFS search path", || Err(err)),
+    }
+
+    println!(
+        "{} of {} checks passed",
+        doctor.checks - doctor.failures,
+        doctor.checks
+    );
+
+    doctor.failures == 0
+}
@@ -0,0 +1,211 @@
+use crate::logging;
+use crate::time::UtcDateTime;
+use anyhow::Result;
+use jni::objects::{JObject, JString, JValue};
+use jni::JNIEnv;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+
+pub const CRASH_LOG_FILE: &str = "openil2-crash.log";
+
+// Guards against cause chains which refer back to an earlier exception
+const MAX_CAUSES: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct ThrowableInfo {
+    pub class_name: String,
+    pub message: Option<String>,
+}
+
+impl fmt::Display for ThrowableInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.class_name, message),
+            None => write!(f, "{}", self.class_name),
+        }
+    }
+}
+
+/// The details of a Java exception that was thrown during a JNI call
+#[derive(Debug, Clone)]
+pub struct JavaException {
+    pub exception: ThrowableInfo,
+    pub causes: Vec<ThrowableInfo>,
+    pub stack_trace: Option<String>,
+}
+
+impl fmt::Display for JavaException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Java exception {}", self.exception)?;
+
+        for cause in self.causes.iter() {
+            write!(f, "\nCaused by: {}", cause)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for JavaException {}
+
+fn java_string(env: JNIEnv<'_>, obj: JObject<'_>) -> jni::errors::Result<Option<String>> {
+    if obj.is_null() {
+        Ok(None)
+    } else {
+        Ok(Some(env.get_string(JString::from(obj))?.into()))
+    }
+}
+
+fn call_string_method(
+    env: JNIEnv<'_>,
+    obj: JObject<'_>,
+    name: &str,
+) -> jni::errors::Result<Option<String>> {
+    let value = env
+        .call_method(obj, name, "()Ljava/lang/String;", &[])?
+        .l()?;
+
+    java_string(env, value)
+}
+
+fn throwable_info(env: JNIEnv<'_>, throwable: JObject<'_>) -> jni::errors::Result<ThrowableInfo> {
+    let class = env
+        .call_method(throwable, "getClass", "()Ljava/lang/Class;", &[])?
+        .l()?;
+
+    let class_name = call_string_method(env, class, "getName")?.unwrap_or_default();
+    let message = call_string_method(env, throwable, "getMessage")?;
+
+    Ok(ThrowableInfo {
+        class_name,
+        message,
+    })
+}
+
+fn causes(env: JNIEnv<'_>, throwable: JObject<'_>) -> jni::errors::Result<Vec<ThrowableInfo>> {
+    let mut causes = Vec::new();
+    let mut current = throwable;
+
+    while causes.len() < MAX_CAUSES {
+        let cause = env
+            .call_method(current, "getCause", "()Ljava/lang/Throwable;", &[])?
+            .l()?;
+
+        if cause.is_null() || env.is_same_object(cause, current)? {
+            break;
+        }
+
+        causes.push(throwable_info(env, cause)?);
+        current = cause;
+    }
+
+    Ok(causes)
+}
+
+fn stack_trace(env: JNIEnv<'_>, throwable: JObject<'_>) -> jni::errors::Result<Option<String>> {
+    let writer = env.new_object("java/io/StringWriter", "()V", &[])?;
+
+    let print_writer = env.new_object(
+        "java/io/PrintWriter",
+        "(Ljava/io/Writer;)V",
+        &[JValue::Object(writer)],
+    )?;
+
+    env.call_method(
+        throwable,
+        "printStackTrace",
+        "(Ljava/io/PrintWriter;)V",
+        &[JValue::Object(print_writer)],
+    )?;
+
+    call_string_method(env, writer, "toString")
+}
+
+/// Clear any exception thrown by a call made while describing another exception
+fn ok_or_clear<T>(env: JNIEnv<'_>, result: jni::errors::Result<T>) -> Option<T> {
+    result
+        .map_err(|_| {
+            let _ = env.exception_clear();
+        })
+        .ok()
+}
+
+/// Clear the pending Java exception, if there is one, and collect its details
+pub fn take_exception(env: JNIEnv<'_>) -> Option<JavaException> {
+    if !env.exception_check().unwrap_or(false) {
+        return None;
+    }
+
+    let throwable = env.exception_occurred().ok()?;
+
+    env.exception_clear().ok()?;
+
+    let throwable = JObject::from(throwable);
+
+    let exception = ok_or_clear(env, throwable_info(env, throwable)).unwrap_or(ThrowableInfo {
+        class_name: "java.lang.Throwable".to_string(),
+        message: None,
+    });
+
+    let causes = ok_or_clear(env, causes(env, throwable)).unwrap_or_default();
+
+    let stack_trace = ok_or_clear(env, stack_trace(env, throwable)).flatten();
+
+    Some(JavaException {
+        exception,
+        causes,
+        stack_trace,
+    })
+}
+
+pub trait JavaContext<T> {
+    /// Wrap a JNI error with the given context, attaching the details of
+    /// the Java exception that caused it if there is one
+    fn java_context<C>(self, env: JNIEnv<'_>, context: C) -> Result<T>
+    where
+        C: fmt::Display + Send + Sync + 'static;
+}
+
+impl<T> JavaContext<T> for jni::errors::Result<T> {
+    fn java_context<C>(self, env: JNIEnv<'_>, context: C) -> Result<T>
+    where
+        C: fmt::Display + Send + Sync + 'static,
+    {
+        self.map_err(|err| match take_exception(env) {
+            Some(exception) => anyhow::Error::new(exception).context(context),
+            None => anyhow::Error::new(err).context(context),
+        })
+    }
+}
+
+/// Write the details of an error caused by a Java exception to the crash log.
+///
+/// Returns `false` if the error wasn't caused by a Java exception.
+pub fn write_crash_log(error: &anyhow::Error) -> io::Result<bool> {
+    let exception = match error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<JavaException>())
+    {
+        Some(exception) => exception,
+        None => return Ok(false),
+    };
+
+    let mut crash_log = File::create(logging::output_path(CRASH_LOG_FILE))?;
+
+    writeln!(
+        crash_log,
+        "OpenIL2 {} crashed at {}",
+        env!("VERGEN_SEMVER_LIGHTWEIGHT"),
+        UtcDateTime::now().rfc3339()
+    )?;
+    writeln!(crash_log)?;
+    writeln!(crash_log, "{:?}", error)?;
+
+    if let Some(stack_trace) = exception.stack_trace.as_ref() {
+        writeln!(crash_log)?;
+        writeln!(crash_log, "{}", stack_trace)?;
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,169 @@
+use crate::time::UtcDateTime;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Marks a write directory as in use by a running launcher
+pub const LOCK_FILE: &str = "openil2.lock";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct LockInfo {
+    pid: u32,
+    started_at: String,
+}
+
+/// The error for a launch that is refused because another launcher holds the lock
+#[derive(Debug, Clone)]
+pub struct AlreadyRunning {
+    write_dir: PathBuf,
+    holder: Option<LockInfo>,
+}
+
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.holder.as_ref() {
+            Some(holder) => write!(
+                f,
+                "OpenIL2 is already running as process {}, started at {}, and writing to {}.",
+                holder.pid,
+                holder.started_at,
+                self.write_dir.display()
+            )?,
+            None => write!(
+                f,
+                "OpenIL2 is already running and writing to {}.",
+                self.write_dir.display()
+            )?,
+        }
+
+        write!(
+            f,
+            "\nClose it first, or use --allow-multiple with a different profile or write directory."
+        )
+    }
+}
+
+impl std::error::Error for AlreadyRunning {}
+
+/// Take an exclusive lock on a file without waiting, returning `false` if
+/// another process holds it. The lock is released when the file is closed,
+/// including when the process ends without running destructors.
+#[cfg(windows)]
+fn try_lock(file: &File) -> io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::shared::winerror::ERROR_LOCK_VIOLATION;
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, OVERLAPPED};
+
+    // Windows locks stop other processes reading the locked bytes, so a byte far
+    // past the end of the file is locked, leaving the contents readable
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+
+    unsafe {
+        overlapped.u.s_mut().OffsetHigh = 1;
+    }
+
+    let locked = unsafe {
+        LockFileEx(
+            file.as_raw_handle() as _,
+            LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+            0,
+            1,
+            0,
+            &mut overlapped,
+        ) != 0
+    };
+
+    if locked {
+        Ok(true)
+    } else {
+        let err = io::Error::last_os_error();
+
+        if err.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) {
+            Ok(false)
+        } else {
+            Err(err)
+        }
+    }
+}
+
+/// Take an exclusive lock on a file without waiting, returning `false` if
+/// another process holds it. The lock is released when the file is closed,
+/// including when the process ends without running destructors.
+#[cfg(unix)]
+fn try_lock(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        Ok(true)
+    } else {
+        let err = io::Error::last_os_error();
+
+        if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+            Ok(false)
+        } else {
+            Err(err)
+        }
+    }
+}
+
+fn read_lock(file: &mut File) -> Option<LockInfo> {
+    let mut contents = String::new();
+
+    file.read_to_string(&mut contents).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Holds the lock on the lock file in a write directory until it is dropped
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Take the lock on the lock file in a write directory, failing with
+    /// [`AlreadyRunning`] if another launcher holds it.
+    ///
+    /// The lock belongs to the process rather than the file, so a lock file left
+    /// behind by a launcher that crashed, or whose process ID has been reused,
+    /// is simply taken over.
+    pub fn acquire(write_dir: &Path) -> Result<InstanceLock> {
+        std::fs::create_dir_all(write_dir)
+            .with_context(|| format!("Unable to create write directory {}", write_dir.display()))?;
+
+        let path = write_dir.join(LOCK_FILE);
+
+        // The file isn't truncated until the lock is held, as it describes the holder
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Unable to open {}", path.display()))?;
+
+        if !try_lock(&file).with_context(|| format!("Unable to lock {}", path.display()))? {
+            return Err(AlreadyRunning {
+                write_dir: write_dir.to_path_buf(),
+                holder: read_lock(&mut file),
+            }
+            .into());
+        }
+
+        let info = LockInfo {
+            pid: std::process::id(),
+            started_at: UtcDateTime::now().rfc3339(),
+        };
+        let json = serde_json::to_string(&info).context("Unable to write the lock file as JSON")?;
+
+        file.set_len(0)
+            .and_then(|()| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(json.as_bytes()))
+            .with_context(|| format!("Unable to write {}", path.display()))?;
+
+        Ok(InstanceLock { _file: file })
+    }
+}
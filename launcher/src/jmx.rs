@@ -0,0 +1,264 @@
+use crate::config::{DebugConfig, CONFIG_FILE, DEFAULT_JMX_PORT};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_JMX_BIND_ADDRESS: &str = "127.0.0.1";
+
+/// Where the generated password and access files are written
+/// when no other location is configured
+pub const DEFAULT_PASSWORD_FILE: &str = "jmxremote.password";
+pub const DEFAULT_ACCESS_FILE: &str = "jmxremote.access";
+
+/// Holds the keystore settings, which are passed to the JVM in a file
+/// so that the keystore password doesn't appear in its command line
+pub const SSL_CONFIG_FILE: &str = "jmxremote.ssl.properties";
+
+/// The keystore password, which is used instead of `jmx-keystore-password`
+/// to keep it out of `openil2.toml`
+pub const KEYSTORE_PASSWORD_ENV: &str = "OPENIL2_JMX_KEYSTORE_PASSWORD";
+
+/// The user whose credentials are generated, which can only read values
+const GENERATED_USER: &str = "monitor";
+
+const GENERATED_PASSWORD_LEN: usize = 24;
+
+const PASSWORD_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+fn generate_password() -> Result<String> {
+    let mut bytes = [0u8; GENERATED_PASSWORD_LEN];
+
+    getrandom::getrandom(&mut bytes)
+        .map_err(|err| anyhow!("Unable to generate a JMX password: {}", err))?;
+
+    // 62 characters don't divide 256 evenly, but the bias is too small to matter here
+    Ok(bytes
+        .iter()
+        .map(|byte| PASSWORD_CHARS[*byte as usize % PASSWORD_CHARS.len()] as char)
+        .collect())
+}
+
+/// Write a file which only the current user can read, as the JVM refuses
+/// to use password files which other users can read
+fn write_private_file(path: &Path, contents: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Unable to create {}", path.display()))?;
+
+    file.write_all(contents.as_bytes())
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+
+    restrict_to_current_user(path)
+}
+
+#[cfg(windows)]
+fn restrict_to_current_user(path: &Path) -> Result<()> {
+    let user_name = std::env::var("USERNAME").context("Unable to get the current user name")?;
+
+    // A bare user name may resolve to a different account, e.g. a local user
+    // with the same name as a domain user
+    let account = match std::env::var("USERDOMAIN") {
+        Ok(domain) => format!("{}\\{}", domain, user_name),
+        Err(_) => user_name,
+    };
+
+    // Remove the permissions inherited from the game directory
+    // and give the current user sole access
+    let status = std::process::Command::new("icacls")
+        .arg(path)
+        .args(&["/inheritance:r", "/grant:r"])
+        .arg(format!("{}:F", account))
+        .status()
+        .context("Unable to run icacls")?;
+
+    if !status.success() {
+        bail!("Unable to restrict access to {}", path.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn restrict_to_current_user(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Escape a value for a Java properties file
+fn properties_value(value: &str) -> String {
+    value.replace("\\", "\\\\")
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .with_context(|| format!("{} is not a valid UTF-8 path", path.display()))
+}
+
+/// Generate whichever of the password and access files don't exist yet, so that
+/// the credentials stay the same from one launch to the next
+fn generate_credentials(password_file: &Path, access_file: &Path) -> Result<()> {
+    if !password_file.exists() {
+        let password = generate_password()?;
+
+        write_private_file(password_file, &format!("{} {}\n", GENERATED_USER, password))?;
+
+        info!(
+            "Generated JMX credentials for the user {} in {}",
+            GENERATED_USER,
+            password_file.display()
+        );
+    }
+
+    // The JVM refuses to start if the access file is missing
+    if !access_file.exists() {
+        write_private_file(access_file, &format!("{} readonly\n", GENERATED_USER))?;
+
+        info!(
+            "Generated JMX access for the user {} in {}",
+            GENERATED_USER,
+            access_file.display()
+        );
+    }
+
+    Ok(())
+}
+
+fn is_loopback(bind_address: &str) -> bool {
+    match bind_address.parse::<IpAddr>() {
+        Ok(address) => address.is_loopback(),
+        Err(_) => bind_address.eq_ignore_ascii_case("localhost"),
+    }
+}
+
+fn is_wildcard(bind_address: &str) -> bool {
+    bind_address
+        .parse::<IpAddr>()
+        .map(|address| address.is_unspecified())
+        .unwrap_or(false)
+}
+
+/// The JVM options which enable remote JMX monitoring, generating the
+/// files that they refer to where needed.
+///
+/// Monitoring from other machines must be authenticated.
+pub fn jmx_options(debug: &DebugConfig) -> Result<Vec<String>> {
+    let jmx_port = debug.jmx_port.unwrap_or(DEFAULT_JMX_PORT);
+    let bind_address = debug
+        .jmx_bind_address
+        .as_deref()
+        .unwrap_or(DEFAULT_JMX_BIND_ADDRESS);
+
+    let mut password_file = debug.jmx_password_file.clone();
+    let mut access_file = debug.jmx_access_file.clone();
+
+    if debug.jmx_generate_credentials {
+        let generated_password_file =
+            password_file.get_or_insert_with(|| PathBuf::from(DEFAULT_PASSWORD_FILE));
+        let generated_access_file = access_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_ACCESS_FILE));
+
+        generate_credentials(generated_password_file, &generated_access_file)?;
+
+        access_file = Some(generated_access_file);
+    }
+
+    if password_file.is_none() && !is_loopback(bind_address) {
+        bail!(
+            "JMX monitoring on {} must be authenticated, set jmx-password-file or jmx-generate-credentials",
+            bind_address
+        );
+    }
+
+    let mut options = vec![
+        format!("-Dcom.sun.management.jmxremote.host={}", bind_address),
+        format!("-Dcom.sun.management.jmxremote.port={}", jmx_port),
+        format!("-Dcom.sun.management.jmxremote.rmi.port={}", jmx_port),
+    ];
+
+    // Clients connect to the address that the RMI stub advertises,
+    // which is only right by default on a single-homed host
+    if !is_loopback(bind_address) && !is_wildcard(bind_address) {
+        options.push(format!("-Djava.rmi.server.hostname={}", bind_address));
+    }
+
+    match password_file.as_ref() {
+        Some(password_file) => {
+            options.push("-Dcom.sun.management.jmxremote.authenticate=true".to_string());
+            options.push(format!(
+                "-Dcom.sun.management.jmxremote.password.file={}",
+                path_str(password_file)?
+            ));
+
+            if let Some(access_file) = access_file.as_ref() {
+                options.push(format!(
+                    "-Dcom.sun.management.jmxremote.access.file={}",
+                    path_str(access_file)?
+                ));
+            }
+        }
+        None => options.push("-Dcom.sun.management.jmxremote.authenticate=false".to_string()),
+    }
+
+    match debug.jmx_keystore.as_ref() {
+        Some(keystore) => {
+            let keystore_password = match std::env::var(KEYSTORE_PASSWORD_ENV) {
+                Ok(keystore_password) => Some(keystore_password),
+                Err(_) => {
+                    if debug.jmx_keystore_password.is_some() {
+                        warn!(
+                            "The JMX keystore password is stored in plain text in {}, set {} instead",
+                            CONFIG_FILE, KEYSTORE_PASSWORD_ENV
+                        );
+                    }
+
+                    debug.jmx_keystore_password.clone()
+                }
+            };
+
+            let mut ssl_config = format!(
+                "javax.net.ssl.keyStore={}\n",
+                properties_value(path_str(keystore)?)
+            );
+
+            if let Some(keystore_password) = keystore_password {
+                ssl_config.push_str(&format!(
+                    "javax.net.ssl.keyStorePassword={}\n",
+                    properties_value(&keystore_password)
+                ));
+            }
+
+            write_private_file(Path::new(SSL_CONFIG_FILE), &ssl_config)?;
+
+            options.push("-Dcom.sun.management.jmxremote.ssl=true".to_string());
+            options.push("-Dcom.sun.management.jmxremote.registry.ssl=true".to_string());
+            options.push(format!(
+                "-Dcom.sun.management.jmxremote.ssl.config.file={}",
+                SSL_CONFIG_FILE
+            ));
+        }
+        None => {
+            if !is_loopback(bind_address) {
+                warn!(
+                    "JMX monitoring on {} doesn't use SSL, so passwords are sent in the clear; set jmx-keystore to use SSL",
+                    bind_address
+                );
+            }
+
+            options.push("-Dcom.sun.management.jmxremote.ssl=false".to_string());
+        }
+    }
+
+    Ok(options)
+}
@@ -0,0 +1,422 @@
+use crate::config::Config;
+use crate::logging;
+use anyhow::{anyhow, bail, Context, Result};
+use jni::errors::jni_error_code_to_result;
+use jni::{sys, JavaVM};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[cfg(windows)]
+const JVM_LIBRARY: &str = "bin/server/jvm.dll";
+
+#[cfg(target_os = "macos")]
+const JVM_LIBRARY: &str = "lib/server/libjvm.dylib";
+
+#[cfg(all(unix, not(target_os = "macos")))]
+const JVM_LIBRARY: &str = "lib/server/libjvm.so";
+
+pub const JAVA_HOME_ENV: &str = "JAVA_HOME";
+
+/// The game classes added by the launcher require Java 11
+pub const MIN_JAVA_VERSION: u32 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct JavaVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub security: u32,
+}
+
+impl JavaVersion {
+    pub fn new(major: u32, minor: u32, security: u32) -> JavaVersion {
+        JavaVersion {
+            major,
+            minor,
+            security,
+        }
+    }
+
+    /// Parse a version string such as `11.0.9.1` or the older `1.8.0_292`
+    pub fn parse(version: &str) -> Option<JavaVersion> {
+        let numbers: Vec<u32> = version
+            .split(|c: char| !c.is_ascii_digit())
+            .take_while(|number| !number.is_empty())
+            .map(|number| number.parse().ok())
+            .collect::<Option<_>>()?;
+
+        match numbers.as_slice() {
+            [1, major, minor, security, ..] => Some(JavaVersion::new(*major, *minor, *security)),
+            [major, minor, security, ..] => Some(JavaVersion::new(*major, *minor, *security)),
+            [major, minor] => Some(JavaVersion::new(*major, *minor, 0)),
+            [major] => Some(JavaVersion::new(*major, 0, 0)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for JavaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.security)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeSource {
+    Configured,
+    Bundled,
+    JavaHome,
+}
+
+impl fmt::Display for RuntimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeSource::Configured => write!(f, "configured"),
+            RuntimeSource::Bundled => write!(f, "bundled"),
+            RuntimeSource::JavaHome => write!(f, "from {}", JAVA_HOME_ENV),
+        }
+    }
+}
+
+/// A Java runtime, described by the `release` file in its home directory
+#[derive(Debug, Clone)]
+pub struct JavaRuntime {
+    pub source: RuntimeSource,
+    pub home: PathBuf,
+    pub library: PathBuf,
+    pub version: Option<JavaVersion>,
+    pub arch: Option<String>,
+    pub implementor: Option<String>,
+}
+
+impl fmt::Display for JavaRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            Some(version) => write!(f, "Java {}", version)?,
+            None => write!(f, "Java of unknown version")?,
+        }
+
+        write!(f, " ({}) at {}", self.source, self.home.display())
+    }
+}
+
+/// Map the architecture names used by Java onto those used by Rust
+fn normalise_arch(arch: &str) -> &str {
+    match arch {
+        "amd64" => "x86_64",
+        "i386" | "i486" | "i586" | "i686" => "x86",
+        "arm64" => "aarch64",
+        other => other,
+    }
+}
+
+/// Read the `KEY="value"` pairs from the `release` file of a Java runtime
+fn read_release(home: &Path) -> Result<HashMap<String, String>> {
+    let release_path = home.join("release");
+
+    if !release_path.is_file() {
+        return Ok(HashMap::new());
+    }
+
+    let release = std::fs::read_to_string(&release_path)
+        .with_context(|| format!("Unable to read {}", release_path.display()))?;
+
+    let properties = release
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_at(line.find('=')?);
+            Some((
+                key.trim().to_string(),
+                value[1..].trim().trim_matches('"').to_string(),
+            ))
+        })
+        .collect();
+
+    Ok(properties)
+}
+
+impl JavaRuntime {
+    fn at(home: &Path, source: RuntimeSource) -> Result<JavaRuntime> {
+        let library = home.join(JVM_LIBRARY);
+
+        if !library.is_file() {
+            bail!(
+                "Unable to find {} in the {} Java runtime at {}",
+                JVM_LIBRARY,
+                source,
+                home.display()
+            );
+        }
+
+        let mut release = read_release(home)?;
+
+        Ok(JavaRuntime {
+            source,
+            home: home.to_path_buf(),
+            library,
+            version: release
+                .get("JAVA_VERSION")
+                .and_then(|version| JavaVersion::parse(version)),
+            arch: release.remove("OS_ARCH"),
+            implementor: release.remove("IMPLEMENTOR"),
+        })
+    }
+
+    /// Check that the runtime can run the game, where it describes itself
+    pub fn validate(&self) -> Result<()> {
+        if let Some(version) = self.version {
+            if version.major < MIN_JAVA_VERSION {
+                bail!(
+                    "{} is too old, the game requires Java {} or later",
+                    self,
+                    MIN_JAVA_VERSION
+                );
+            }
+        }
+
+        if let Some(arch) = self.arch.as_deref() {
+            if normalise_arch(arch) != env::consts::ARCH {
+                bail!(
+                    "{} is built for {} but the launcher is built for {}",
+                    self,
+                    arch,
+                    env::consts::ARCH
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_oracle(&self) -> bool {
+        self.implementor
+            .as_deref()
+            .map(|implementor| implementor.starts_with("Oracle"))
+            .unwrap_or(false)
+    }
+
+    /// Whether the runtime accepts a JVM option; options are assumed to be
+    /// supported when the runtime version is unknown
+    pub fn supports_option(&self, option: &str) -> bool {
+        let version = match self.version {
+            Some(version) => version,
+            None => return true,
+        };
+
+        match option {
+            // Shenandoah was backported to JDK 11 in 11.0.9,
+            // but it is left out of Oracle's builds
+            "-XX:+UseShenandoahGC" => version >= JavaVersion::new(11, 0, 9) && !self.is_oracle(),
+            // ZGC is experimental before JDK 15
+            "-XX:+UseZGC" => version.major >= 15,
+            // Biased locking is obsolete from JDK 18
+            "-XX:+UseBiasedLocking" | "-XX:-UseBiasedLocking" => version.major < 18,
+            _ => true,
+        }
+    }
+
+    /// Split JVM options into those that the runtime supports and those it doesn't
+    pub fn filter_options(&self, options: Vec<String>) -> (Vec<String>, Vec<String>) {
+        options
+            .into_iter()
+            .partition(|option| self.supports_option(option))
+    }
+
+    #[cfg(windows)]
+    pub fn load_library(&self) -> Result<lib::Library> {
+        use lib::os::windows::{Library, LOAD_WITH_ALTERED_SEARCH_PATH};
+
+        // Search the runtime's own directories for the libraries that jvm.dll
+        // depends on, which requires an absolute path
+        let library = env::current_dir()
+            .context("Unable to get current directory")?
+            .join(&self.library);
+
+        Library::load_with_flags(&library, LOAD_WITH_ALTERED_SEARCH_PATH)
+            .map(lib::Library::from)
+            .with_context(|| format!("Unable to load {}", self.library.display()))
+    }
+
+    #[cfg(not(windows))]
+    pub fn load_library(&self) -> Result<lib::Library> {
+        lib::Library::new(&self.library)
+            .with_context(|| format!("Unable to load {}", self.library.display()))
+    }
+}
+
+/// Find the Java runtime to launch the game with, trying the runtime configured
+/// in `openil2.toml`, then the runtime bundled with the launcher, then `JAVA_HOME`
+pub fn discover(config: &Config) -> Result<JavaRuntime> {
+    if let Some(java_home) = config.jvm.java_home.as_ref() {
+        return JavaRuntime::at(java_home, RuntimeSource::Configured);
+    }
+
+    if let Ok(runtime) = JavaRuntime::at(Path::new("."), RuntimeSource::Bundled) {
+        return Ok(runtime);
+    }
+
+    match env::var_os(JAVA_HOME_ENV) {
+        Some(java_home) => JavaRuntime::at(Path::new(&java_home), RuntimeSource::JavaHome),
+        None => bail!(
+            "Unable to find a Java runtime: {} is not bundled with the launcher and {} is not set",
+            JVM_LIBRARY,
+            JAVA_HOME_ENV
+        ),
+    }
+}
+
+/// Called by the JVM when the game calls `System.exit`, just before the process exits
+extern "system" fn exit_hook(code: sys::jint) {
+    info!("The game exited with code {}", code);
+    log::logger().flush();
+}
+
+/// The log file, opened before the JVM is created so that the abort hook can
+/// write to it without allocating or taking the logger's lock
+static ABORT_LOG: Mutex<Option<File>> = Mutex::new(None);
+
+const ABORT_MESSAGE: &[u8] = b"ERROR openil2::jvm: The Java VM aborted, \
+see the hs_err_pid log in the game directory for details\n";
+
+/// Called by the JVM when it crashes, just before the process aborts.
+///
+/// The JVM may be in any state, so this only writes a fixed message to the
+/// log file; the lock file is left behind and replaced by the next launch.
+extern "system" fn abort_hook() {
+    if let Ok(abort_log) = ABORT_LOG.try_lock() {
+        if let Some(mut file) = abort_log.as_ref() {
+            let _ = file.write_all(ABORT_MESSAGE);
+        }
+    }
+}
+
+/// The JVM options which install the exit and abort hooks, so that the way the
+/// game ended is logged even when the JVM ends the process itself
+pub fn hook_options() -> [sys::JavaVMOption; 2] {
+    let log_file = logging::output_path(logging::LOG_FILE);
+
+    match OpenOptions::new().append(true).open(&log_file) {
+        Ok(file) => *ABORT_LOG.lock().unwrap_or_else(|err| err.into_inner()) = Some(file),
+        Err(err) => warn!(
+            "Unable to open {} for the abort hook: {}",
+            log_file.display(),
+            err
+        ),
+    }
+
+    let exit_hook: extern "system" fn(sys::jint) = exit_hook;
+    let abort_hook: extern "system" fn() = abort_hook;
+
+    [
+        sys::JavaVMOption {
+            optionString: b"exit\0".as_ptr() as *mut c_char,
+            extraInfo: exit_hook as *mut c_void,
+        },
+        sys::JavaVMOption {
+            optionString: b"abort\0".as_ptr() as *mut c_char,
+            extraInfo: abort_hook as *mut c_void,
+        },
+    ]
+}
+
+/// Shut down the JVM once the game's main method has returned, waiting for
+/// the game's other threads to finish in the same way as the java launcher
+pub fn destroy_java_vm(java_vm: JavaVM) -> Result<()> {
+    let raw_java_vm = java_vm.get_java_vm_pointer();
+
+    unsafe {
+        // Detach the main thread so that the JVM doesn't wait for it to finish.
+        // The thread that created the JVM is attached by JNI_CreateJavaVM, so the
+        // guard returned by attach_current_thread doesn't detach it when dropped
+        if let Some(detach_current_thread) = (**raw_java_vm).DetachCurrentThread {
+            detach_current_thread(raw_java_vm);
+        }
+
+        let destroy_java_vm = (**raw_java_vm)
+            .DestroyJavaVM
+            .ok_or_else(|| anyhow!("Unable to find DestroyJavaVM function"))?;
+
+        jni_error_code_to_result(destroy_java_vm(raw_java_vm)).context("Error destroying Java VM")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime(version: &str, implementor: Option<&str>) -> JavaRuntime {
+        JavaRuntime {
+            source: RuntimeSource::Bundled,
+            home: PathBuf::from("."),
+            library: PathBuf::from(JVM_LIBRARY),
+            version: JavaVersion::parse(version),
+            arch: None,
+            implementor: implementor.map(|implementor| implementor.to_string()),
+        }
+    }
+
+    #[test]
+    fn parses_java_versions() {
+        assert_eq!(
+            JavaVersion::parse("1.8.0_292"),
+            Some(JavaVersion::new(8, 0, 292))
+        );
+        assert_eq!(
+            JavaVersion::parse("11.0.8"),
+            Some(JavaVersion::new(11, 0, 8))
+        );
+        assert_eq!(
+            JavaVersion::parse("11.0.9.1"),
+            Some(JavaVersion::new(11, 0, 9))
+        );
+        assert_eq!(JavaVersion::parse("17"), Some(JavaVersion::new(17, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_malformed_java_versions() {
+        assert_eq!(JavaVersion::parse(""), None);
+        assert_eq!(JavaVersion::parse("unknown"), None);
+        assert_eq!(JavaVersion::parse(".11"), None);
+        assert_eq!(JavaVersion::parse("99999999999"), None);
+    }
+
+    #[test]
+    fn shenandoah_requires_11_0_9() {
+        let shenandoah = "-XX:+UseShenandoahGC";
+
+        assert!(!runtime("1.8.0_292", None).supports_option(shenandoah));
+        assert!(!runtime("11.0.8", None).supports_option(shenandoah));
+        assert!(runtime("11.0.9", None).supports_option(shenandoah));
+        assert!(runtime("17", None).supports_option(shenandoah));
+    }
+
+    #[test]
+    fn shenandoah_is_missing_from_oracle_builds() {
+        let oracle = Some("Oracle Corporation");
+
+        assert!(!runtime("17", oracle).supports_option("-XX:+UseShenandoahGC"));
+        assert!(runtime("17", oracle).supports_option("-XX:+UseZGC"));
+    }
+
+    #[test]
+    fn options_are_supported_by_unknown_versions() {
+        let unknown = runtime("unknown", None);
+
+        assert!(unknown.supports_option("-XX:+UseShenandoahGC"));
+        assert!(unknown.supports_option("-XX:+UseZGC"));
+    }
+
+    #[test]
+    fn gates_options_on_version() {
+        assert!(!runtime("11.0.9", None).supports_option("-XX:+UseZGC"));
+        assert!(runtime("15", None).supports_option("-XX:+UseZGC"));
+        assert!(runtime("17", None).supports_option("-XX:-UseBiasedLocking"));
+        assert!(!runtime("18", None).supports_option("-XX:-UseBiasedLocking"));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::build_info;
+use crate::logging;
+use crate::time::UtcDateTime;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::time::Instant;
+
+pub const LAUNCH_REPORT_FILE: &str = "launch-report.json";
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LauncherInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub build_timestamp: &'static str,
+    pub target: &'static str,
+    pub profile: &'static str,
+    pub rustc_version: &'static str,
+}
+
+impl LauncherInfo {
+    fn current() -> LauncherInfo {
+        LauncherInfo {
+            version: env!("VERGEN_SEMVER_LIGHTWEIGHT"),
+            git_sha: env!("VERGEN_SHA"),
+            build_timestamp: env!("VERGEN_BUILD_TIMESTAMP"),
+            target: build_info::TARGET,
+            profile: build_info::PROFILE,
+            rustc_version: build_info::RUSTC_VERSION,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PhaseTiming {
+    pub name: &'static str,
+    pub duration_ms: f64,
+}
+
+/// What the launcher did to start the game and how long each phase took,
+/// written to `launch-report.json` before the game's main method is entered
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LaunchReport {
+    pub started_at: String,
+    pub launcher: LauncherInfo,
+    pub java_runtime: Option<String>,
+    pub preset: Option<String>,
+    pub main_class: String,
+    pub class_path: Option<String>,
+    pub jvm_options: Vec<String>,
+    pub search_path: Vec<String>,
+    pub write_dir: Option<String>,
+    pub phases: Vec<PhaseTiming>,
+    /// The time from the start of the launch until the game's main method is entered
+    pub total_ms: f64,
+    /// The error which stopped the launch, if the game's main method wasn't reached
+    pub error: Option<String>,
+    #[serde(skip)]
+    start: Instant,
+    #[serde(skip)]
+    written: bool,
+}
+
+fn millis_since(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+impl LaunchReport {
+    pub fn new(main_class: &str) -> LaunchReport {
+        LaunchReport {
+            started_at: UtcDateTime::now().rfc3339(),
+            launcher: LauncherInfo::current(),
+            java_runtime: None,
+            preset: None,
+            main_class: main_class.to_string(),
+            class_path: None,
+            jvm_options: Vec::new(),
+            search_path: Vec::new(),
+            write_dir: None,
+            phases: Vec::new(),
+            total_ms: 0.0,
+            error: None,
+            start: Instant::now(),
+            written: false,
+        }
+    }
+
+    /// Run a phase of the launch, recording how long it took even if it fails
+    pub fn time<T, F>(&mut self, name: &'static str, phase: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let start = Instant::now();
+        let result = phase();
+
+        self.phases.push(PhaseTiming {
+            name,
+            duration_ms: millis_since(start),
+        });
+
+        result
+    }
+
+    pub fn is_written(&self) -> bool {
+        self.written
+    }
+
+    pub fn write(&mut self) -> Result<()> {
+        self.total_ms = millis_since(self.start);
+        self.written = true;
+
+        let json = serde_json::to_string_pretty(self)
+            .context("Unable to write the launch report as JSON")?;
+
+        let path = logging::output_path(LAUNCH_REPORT_FILE);
+
+        std::fs::write(&path, json).with_context(|| format!("Unable to write {}", path.display()))
+    }
+}
@@ -0,0 +1,267 @@
+use crate::time::UtcDateTime;
+use anyhow::{anyhow, Context, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const LOG_FILE: &str = "openil2.log";
+
+pub const LOG_LEVEL_ENV: &str = "OPENIL2_LOG";
+
+pub const LOG_LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+pub const LAST_ERROR_FILE: &str = "openil2-last-error.txt";
+
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// The number of earlier logs which are kept, as `openil2.1.log`, `openil2.2.log` and so on
+const KEPT_LOGS: usize = 5;
+
+/// Where log records are written
+enum LogOutput {
+    /// Records are held until the launch log is started, once the launcher knows
+    /// that it isn't replacing the log of a game that is still running
+    Pending(Vec<String>),
+    File(File),
+    /// The log file couldn't be opened
+    Closed,
+}
+
+static LOG_OUTPUT: Mutex<LogOutput> = Mutex::new(LogOutput::Pending(Vec::new()));
+
+/// The directory that the log and the launcher's other files are written to,
+/// once the configuration has been loaded
+static OUTPUT_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Writes each record to the log file, and those up to `stderr_level` to stderr,
+/// which is only visible when the launcher has a console
+struct Logger {
+    level: LevelFilter,
+    stderr_level: LevelFilter,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if record.level() <= self.stderr_level {
+            eprintln!("{}", record.args());
+        }
+
+        let line = format!(
+            "{} {:<5} {}: {}",
+            UtcDateTime::now().rfc3339(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        if let Ok(mut output) = LOG_OUTPUT.lock() {
+            match &mut *output {
+                LogOutput::Pending(lines) => lines.push(line),
+                LogOutput::File(file) => {
+                    let _ = writeln!(file, "{}", line);
+                }
+                LogOutput::Closed => {}
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut output) = LOG_OUTPUT.lock() {
+            if let LogOutput::File(file) = &mut *output {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+/// The directory that the launcher writes its log, launch report and error files to,
+/// which is the game directory until [`start_launch_log`] or [`append_to_log`] is called
+pub fn output_dir() -> PathBuf {
+    OUTPUT_DIR
+        .lock()
+        .ok()
+        .and_then(|dir| dir.clone())
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// The path of one of the launcher's files in the output directory
+pub fn output_path(file_name: &str) -> PathBuf {
+    output_dir().join(file_name)
+}
+
+fn set_output_dir(dir: &Path) {
+    if let Ok(mut output_dir) = OUTPUT_DIR.lock() {
+        *output_dir = Some(dir.to_path_buf());
+    }
+}
+
+fn kept_log_file(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("openil2.{}.log", index))
+}
+
+/// Move the earlier logs along, dropping the oldest, so that each run of the launcher
+/// starts a new log
+fn rotate_logs(dir: &Path) -> io::Result<()> {
+    for index in (1..KEPT_LOGS).rev() {
+        let kept_log = kept_log_file(dir, index);
+
+        if kept_log.exists() {
+            std::fs::rename(&kept_log, kept_log_file(dir, index + 1))?;
+        }
+    }
+
+    let log_file = dir.join(LOG_FILE);
+
+    if log_file.exists() {
+        std::fs::rename(&log_file, kept_log_file(dir, 1))?;
+    }
+
+    Ok(())
+}
+
+fn create_log_file(dir: &Path) -> Result<File> {
+    let log_file = dir.join(LOG_FILE);
+
+    rotate_logs(dir).with_context(|| format!("Unable to rotate {}", log_file.display()))?;
+    File::create(&log_file).with_context(|| format!("Unable to create {}", log_file.display()))
+}
+
+/// Add to the current log, which may belong to a game that is still running
+fn open_log_file(dir: &Path) -> Result<File> {
+    let log_file = dir.join(LOG_FILE);
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_file)
+        .with_context(|| format!("Unable to open {}", log_file.display()))
+}
+
+/// Replace the output of the logger, writing out the records held until now
+fn set_log_output(file: Result<File>) {
+    let (new_output, file_err) = match file {
+        Ok(file) => (LogOutput::File(file), None),
+        Err(err) => (LogOutput::Closed, Some(err)),
+    };
+
+    if let Ok(mut output) = LOG_OUTPUT.lock() {
+        let old_output = std::mem::replace(&mut *output, new_output);
+
+        if let (LogOutput::Pending(lines), LogOutput::File(file)) = (old_output, &mut *output) {
+            for line in lines {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    if let Some(err) = file_err {
+        log::warn!("{:#}", err);
+    }
+}
+
+/// Start a new log in the write directory for a launch of the game, keeping the
+/// logs of earlier launches. This is only called once the launcher holds the
+/// instance lock on the directory.
+pub fn start_launch_log(dir: &Path) {
+    set_output_dir(dir);
+    set_log_output(create_log_file(dir));
+}
+
+/// Add to the current log in the write directory, for the commands other than launching the game
+pub fn append_to_log(dir: &Path) {
+    set_output_dir(dir);
+    set_log_output(open_log_file(dir));
+}
+
+/// Write out the records held for a launch log that was never started, adding
+/// them to the current log, so that an error which stops the launcher first
+/// isn't lost
+pub fn flush_pending() {
+    let pending = matches!(LOG_OUTPUT.lock().as_deref(), Ok(LogOutput::Pending(_)));
+
+    if pending {
+        set_log_output(open_log_file(&output_dir()));
+    }
+}
+
+/// The log level from the command line, or else from the `OPENIL2_LOG` environment variable
+pub fn log_level(cli_level: Option<&str>) -> Result<LevelFilter> {
+    match cli_level {
+        Some(level) => level
+            .parse()
+            .map_err(|_| anyhow!("Unknown log level {}", level)),
+        None => match std::env::var(LOG_LEVEL_ENV) {
+            Ok(level) => level.trim().parse().map_err(|_| {
+                anyhow!(
+                    "Unknown log level {} in {}, expected one of {}",
+                    level,
+                    LOG_LEVEL_ENV,
+                    LOG_LEVELS.join(", ")
+                )
+            }),
+            Err(_) => Ok(DEFAULT_LOG_LEVEL),
+        },
+    }
+}
+
+/// Log to `openil2.log`, holding records until the write directory is known.
+/// Launching the game then starts a new log there with [`start_launch_log`],
+/// while other commands add to the current log with [`append_to_log`].
+///
+/// Other commands only print warnings and errors to stderr, unless a log level
+/// is given on the command line, so that their output isn't cluttered.
+///
+/// Problems setting up logging are logged as warnings rather than stopping the
+/// launcher, which falls back to the default level or to logging to stderr alone.
+pub fn init(cli_level: Option<&str>, launching: bool) {
+    let (level, level_err) = match log_level(cli_level) {
+        Ok(level) => (level, None),
+        Err(err) => (DEFAULT_LOG_LEVEL, Some(err)),
+    };
+
+    let stderr_level = if launching || cli_level.is_some() {
+        level
+    } else {
+        level.min(LevelFilter::Warn)
+    };
+
+    let logger = Logger {
+        level,
+        stderr_level,
+    };
+
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(level);
+    }
+
+    if let Some(err) = level_err {
+        log::warn!("{:#}", err);
+    }
+}
+
+/// Write an error that stopped the launcher to a file that users can attach to bug reports
+pub fn write_last_error(error: &anyhow::Error) -> io::Result<()> {
+    let mut last_error = File::create(output_path(LAST_ERROR_FILE))?;
+
+    writeln!(
+        last_error,
+        "OpenIL2 {} failed at {}",
+        env!("VERGEN_SEMVER_LIGHTWEIGHT"),
+        UtcDateTime::now().rfc3339()
+    )?;
+    writeln!(last_error)?;
+    writeln!(last_error, "{:?}", error)?;
+
+    Ok(())
+}
@@ -9,18 +9,51 @@ use jni::objects::*;
 use jni::*;
 extern crate clap;
 extern crate libloading as lib;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use config::Config;
+use exception::JavaContext;
+use instance::{AlreadyRunning, InstanceLock};
+use launch_report::LaunchReport;
+use log::{debug, error, info, warn};
+use std::env;
 use std::io::Read;
-use winapi::shared::minwindef::MAX_PATH;
-use winapi::um::processenv::GetCurrentDirectoryA;
-use winapi::um::winnt::CHAR;
-
-include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+use std::path::Path;
+
+mod config;
+mod conflicts;
+mod console;
+mod doctor;
+mod exception;
+mod instance;
+mod jmx;
+mod jvm;
+mod launch_report;
+mod logging;
+mod modload;
+mod physfs;
+mod preset;
+mod search_path;
+mod time;
+mod vfs;
 
 pub mod build_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
 }
 
+const PHYSFS_JAR: &str = "physfs_java.jar";
+
+const FILES_ZIP: &str = "files.zip";
+
+const CLASS_PATH: [&str; 4] = [".", PHYSFS_JAR, "mods", "mods/*"];
+
+fn java_class_path(config: &Config) -> Result<String> {
+    // Use the platform separator, i.e. ';' on Windows and ':' elsewhere
+    env::join_paths(config.class_path(&CLASS_PATH))
+        .context("Unable to build the Java classpath")?
+        .into_string()
+        .map_err(|_| anyhow!("Unable to convert the Java classpath to a valid UTF-8 string"))
+}
+
 fn get_system_classloader(env: JNIEnv<'_>) -> Result<JObject<'_>> {
     let loader_value = env
         .call_static_method(
@@ -29,12 +62,7 @@ fn get_system_classloader(env: JNIEnv<'_>) -> Result<JObject<'_>> {
             "()Ljava/lang/ClassLoader;",
             &[],
         )
-        .with_context(|| {
-            if env.exception_check().unwrap() {
-                env.exception_describe().unwrap()
-            };
-            "Unable to get system class loader"
-        })?;
+        .java_context(env, "Unable to get system class loader")?;
 
     match loader_value {
         JValue::Object(jobject) => Ok(jobject),
@@ -47,12 +75,9 @@ fn load_class<'a>(
     loader: JObject<'a>,
     class_name_str: &str,
 ) -> Result<JClass<'a>> {
-    let class_name = env.new_string(class_name_str).with_context(|| {
-        if env.exception_check().unwrap() {
-            env.exception_describe().unwrap()
-        };
-        "Unable to create Java String"
-    })?;
+    let class_name = env
+        .new_string(class_name_str)
+        .java_context(env, "Unable to create Java String")?;
 
     let class_value = env
         .call_method(
@@ -61,12 +86,7 @@ fn load_class<'a>(
             "(Ljava/lang/String;Z)Ljava/lang/Class;",
             &[JValue::Object(*class_name), JValue::Bool(1)],
         )
-        .with_context(|| {
-            if env.exception_check().unwrap() {
-                env.exception_describe().unwrap()
-            };
-            "Unable to load class"
-        })?;
+        .java_context(env, format!("Unable to load class {}", class_name_str))?;
 
     match class_value {
         JValue::Object(jobject) => Ok(jobject.into()),
@@ -74,77 +94,43 @@ fn load_class<'a>(
     }
 }
 
-fn load_class_from_physfs_jar<'a>(
-    env: JNIEnv<'a>,
-    loader: JObject<'a>,
-    class_name: &str,
-) -> Result<JClass<'a>> {
-    let binary_name = class_name.to_string().replace(".", "/");
-    let physfs_jar = std::fs::File::open("physfs_java.jar")?;
-    let mut zip_archive = zip::ZipArchive::new(physfs_jar)?;
-    let zip_entry_name = format!("{}.class", binary_name);
-    let mut zip_entry = zip_archive
-        .by_name(&zip_entry_name)
-        .with_context(|| anyhow!("Couldn't find entry {} in the PhysFS JAR", zip_entry_name))?;
-    let mut class_data: Vec<u8> = Vec::new();
-    zip_entry.read_to_end(&mut class_data)?;
-    env.define_class(binary_name, loader, &class_data)
-        .with_context(|| {
-            if env.exception_check().unwrap() {
-                env.exception_describe().unwrap()
-            }
-            "Unable to load class from the PhysFS JAR"
-        })
+/// Where the classes that bootstrap the game are read from
+enum ClassSource {
+    /// physfs_java.jar, which is mounted on the PhysFS search path by the launcher
+    PhysFSJar,
+    /// The game's archives on the PhysFS search path, so that mods can override them
+    SearchPath,
 }
 
-fn load_class_from_zip<'a>(
+/// Read a class through PhysFS and define it in a class loader. PhysFS keeps the
+/// mounted archives open, so each archive's directory is only read once.
+fn define_class<'a>(
     env: JNIEnv<'a>,
     loader: JObject<'a>,
+    source: ClassSource,
     class_name: &str,
 ) -> Result<JClass<'a>> {
-    let binary_name = class_name.to_string().replace(".", "/");
-    let files_zip = std::fs::File::open("files.zip")?;
-    let mut zip_archive = zip::ZipArchive::new(files_zip)?;
-    let zip_entry_name = format!("{}.class", binary_name.to_ascii_uppercase());
-    let mut zip_entry = zip_archive.by_name(&zip_entry_name).with_context(|| {
-        anyhow!(
-            "Couldn't find entry {} in the zip file files.zip",
-            zip_entry_name
-        )
-    })?;
+    let binary_name = class_name.replace(".", "/");
+    let class_file_name = format!("{}.class", binary_name);
+
+    let mut class_file = match source {
+        ClassSource::PhysFSJar => physfs::File::open_exact(&format!(
+            "{}/{}",
+            search_path::PHYSFS_JAR_MOUNT_POINT,
+            class_file_name
+        )),
+        ClassSource::SearchPath => physfs::File::open(&class_file_name),
+    }
+    .with_context(|| format!("Unable to find class {}", class_name))?;
+
     let mut class_data: Vec<u8> = Vec::new();
-    zip_entry.read_to_end(&mut class_data)?;
-    env.define_class(binary_name, loader, &class_data)
-        .with_context(|| {
-            if env.exception_check().unwrap() {
-                env.exception_describe().unwrap()
-            }
-            "Unable to load class from files.zip"
-        })
-}
 
-fn mount_files_zip(env: JNIEnv<'_>, physfs_class: JClass<'_>) -> Result<()> {
-    let files_zip = env.new_string("files.zip").with_context(|| {
-        if env.exception_check().unwrap() {
-            env.exception_describe().unwrap()
-        };
-        "Unable to create Java String"
-    })?;
+    class_file
+        .read_to_end(&mut class_data)
+        .with_context(|| format!("Unable to read class {}", class_name))?;
 
-    env.call_static_method(
-        physfs_class,
-        "mountArchive",
-        "(Ljava/lang/String;)V",
-        &[JValue::Object(*files_zip)],
-    )
-    .with_context(|| {
-        if env.exception_check().unwrap() {
-            env.exception_describe().unwrap()
-        };
-        "Unable to call PhysFS mountArchive method"
-    })?;
-
-    Ok(())
+    env.define_class(binary_name, loader, &class_data)
+        .java_context(env, format!("Unable to define class {}", class_name))
 }
 
 fn call_loader_method<'a>(env: JNIEnv<'a>, physfs_loader_class: JClass<'_>) -> Result<JObject<'a>> {
@@ -155,12 +141,7 @@ fn call_loader_method<'a>(env: JNIEnv<'a>, physfs_loader_class: JClass<'_>) -> R
             "()Ljava/lang/ClassLoader;",
             &[],
         )
-        .with_context(|| {
-            if env.exception_check().unwrap() {
-                env.exception_describe().unwrap()
-            };
-            "Unable to call PhysFSLoader.loader method"
-        })?;
+        .java_context(env, "Unable to call PhysFSLoader.loader method")?;
 
     match return_value {
         JValue::Object(jobject) => Ok(jobject),
@@ -170,37 +151,31 @@ fn call_loader_method<'a>(env: JNIEnv<'a>, physfs_loader_class: JClass<'_>) -> R
 
 fn call_loadnative_method(env: JNIEnv<'_>, inputstream_class: JClass<'_>) -> Result<()> {
     env.call_static_method(inputstream_class, "_loadNative", "()V", &[])
-        .with_context(|| {
-            if env.exception_check().unwrap() {
-                env.exception_describe().unwrap()
-            };
-            "Unable to call _loadNative method"
-        })?;
+        .java_context(env, "Unable to call _loadNative method")?;
 
     Ok(())
 }
 
 fn call_preload_method(env: JNIEnv<'_>, physfs_loader: JObject<'_>) -> Result<()> {
     env.call_method(physfs_loader, "preload", "()V", &[])
-        .with_context(|| {
-            if env.exception_check().unwrap() {
-                env.exception_describe().unwrap()
-            };
-            "Unable to preload game classes"
-        })?;
+        .java_context(env, "Unable to preload game classes")?;
 
     Ok(())
 }
 
-fn call_main_method(env: JNIEnv<'_>, main_class: JClass<'_>) -> Result<()> {
+fn call_main_method(env: JNIEnv<'_>, main_class: JClass<'_>, game_args: &[String]) -> Result<()> {
     let main_args = env
-        .new_object_array(0, "java/lang/String", JObject::null())
-        .with_context(|| {
-            if env.exception_check().unwrap() {
-                env.exception_describe().unwrap()
-            };
-            "Error creating main args array"
-        })?;
+        .new_object_array(game_args.len() as i32, "java/lang/String", JObject::null())
+        .java_context(env, "Error creating main args array")?;
+
+    for (idx, game_arg) in game_args.iter().enumerate() {
+        let game_arg = env
+            .new_string(game_arg)
+            .java_context(env, "Unable to create Java String")?;
+
+        env.set_object_array_element(main_args, idx as i32, game_arg)
+            .java_context(env, "Error populating main args array")?;
+    }
 
     env.call_static_method(
         main_class,
@@ -208,61 +183,69 @@ fn call_main_method(env: JNIEnv<'_>, main_class: JClass<'_>) -> Result<()> {
         "([Ljava/lang/String;)V",
         &[JValue::Object(main_args.into())],
     )
-    .with_context(|| {
-        if env.exception_check().unwrap() {
-            env.exception_describe().unwrap()
-        };
-        "Unable to call main method"
-    })?;
+    .java_context(env, "Unable to call main method")?;
 
     Ok(())
 }
 
-struct PhysFS {}
-
-impl Drop for PhysFS {
-    fn drop(&mut self) {
-        unsafe {
-            if PHYSFS_isInit() > 0 {
-                if PHYSFS_deinit() == 0 {
-                    panic!("Unable to deinitialise PhysFS");
-                }
-            }
-        }
-    }
-}
-
-fn init_physfs() -> Result<PhysFS> {
-    unsafe {
-        if PHYSFS_init(std::ptr::null()) == 0 {
-            bail!("Unable to initialise PhysFS");
-        } else {
-            let mut vec = vec![0 as CHAR; MAX_PATH as usize];
-            let c_str = &mut vec[..];
-
-            if GetCurrentDirectoryA(MAX_PATH as u32, c_str.as_mut_ptr() as *mut CHAR) == 0 {
-                bail!("Unable to get current directory");
-            } else {
-                if PHYSFS_setWriteDir(c_str.as_mut_ptr() as *mut CHAR) == 0 {
-                    bail!("Unable to set current directory as PhysFS write directory");
-                } else {
-                    if PHYSFS_addToSearchPath(c_str.as_mut_ptr() as *mut CHAR, 0) == 0 {
-                        bail!("Unable to add current directory to PhysFS search path");
-                    }
-                }
-            }
-        }
-    }
-
-    Ok(PhysFS {})
-}
-
-fn main() -> Result<()> {
-    let _physfs = init_physfs()?;
-
-    let cli_args = App::new("OpenIL2")
+fn cli_args() -> ArgMatches<'static> {
+    App::new("OpenIL2")
         .version(env!("VERGEN_SEMVER_LIGHTWEIGHT"))
         .about(build_info::PKG_DESCRIPTION)
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .short("p")
+                .takes_value(true)
+                .value_name("name")
+                .help("The profile from openil2.toml to launch the game with"),
+        )
+        .arg(
+            Arg::with_name("main-class")
+                .long("main-class")
+                .takes_value(true)
+                .value_name("class")
+                .help("The main class to launch, default com.maddox.il2.game.GameWin3D"),
+        )
+        .arg(
+            Arg::with_name("server")
+                .long("server")
+                .help("Launch the dedicated server, com.maddox.il2.game.DServer, with a console"),
+        )
+        .arg(
+            Arg::with_name("console")
+                .long("console")
+                .help("Show game output in the console that the launcher was started from, or in a new one"),
+        )
+        .arg(
+            Arg::with_name("write-dir")
+                .long("write-dir")
+                .takes_value(true)
+                .value_name("dir")
+                .help("Write saves, configs and logs to this directory, which is searched before the installation"),
+        )
+        .arg(
+            Arg::with_name("user-write-dir")
+                .long("user-write-dir")
+                .help("Write saves, configs and logs to a directory for the current user and profile"),
+        )
+        .arg(
+            Arg::with_name("allow-multiple")
+                .long("allow-multiple")
+                .help("Allow more than one instance of the game, which should each use a different profile or write directory"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .takes_value(true)
+                .possible_values(&logging::LOG_LEVELS)
+                .help("How much to write to openil2.log, overriding the OPENIL2_LOG environment variable [default: info]"),
+        )
+        .arg(
+            Arg::with_name("print-mounts")
+                .long("print-mounts")
+                .help("Print the PhysFS search path, including the archives from .modload, and exit"),
+        )
         .arg(
             Arg::with_name("jmx-monitoring")
                 .long("jmx-monitoring")
@@ -273,10 +256,42 @@ fn main() -> Result<()> {
             Arg::with_name("jmx-port")
                 .long("jmx-port")
                 .takes_value(true)
-                .default_value_if("jmx-monitoring", None, "9010")
                 .value_name("port")
                 .help("The port to use for JMX monitoring, default 9010"),
         )
+        .arg(
+            Arg::with_name("jmx-bind-address")
+                .long("jmx-bind-address")
+                .takes_value(true)
+                .value_name("address")
+                .help("The address that JMX listens on, default 127.0.0.1; other addresses require authentication"),
+        )
+        .arg(
+            Arg::with_name("jmx-password-file")
+                .long("jmx-password-file")
+                .takes_value(true)
+                .value_name("file")
+                .help("Authenticate JMX clients with the users and passwords in this file"),
+        )
+        .arg(
+            Arg::with_name("jmx-access-file")
+                .long("jmx-access-file")
+                .takes_value(true)
+                .value_name("file")
+                .help("The JMX access roles of the users in the password file"),
+        )
+        .arg(
+            Arg::with_name("jmx-generate-credentials")
+                .long("jmx-generate-credentials")
+                .help("Generate a JMX password file and access file for a read only user named monitor, unless the password file exists"),
+        )
+        .arg(
+            Arg::with_name("jmx-keystore")
+                .long("jmx-keystore")
+                .takes_value(true)
+                .value_name("file")
+                .help("Use SSL for JMX with this keystore, whose password is read from OPENIL2_JMX_KEYSTORE_PASSWORD"),
+        )
         .arg(
             Arg::with_name("await-debug")
                 .long("await-debug")
@@ -287,10 +302,17 @@ fn main() -> Result<()> {
             Arg::with_name("debug-port")
                 .long("debug-port")
                 .takes_value(true)
-                .default_value_if("await-debug", None, "5005")
                 .value_name("port")
                 .help("The port to use for attaching a debugger, default 5005"),
         )
+        .arg(
+            Arg::with_name("preset")
+                .long("preset")
+                .takes_value(true)
+                .possible_values(&preset::PRESET_NAMES)
+                .value_name("name")
+                .help("The garbage collector and heap preset to use, default low-latency"),
+        )
         .arg(
             Arg::with_name("heap-size")
                 .long("heap-size")
@@ -303,86 +325,238 @@ fn main() -> Result<()> {
                 .long("gc-logging")
                 .help("Log GC events to file gc.log in the game directory"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("jfr")
+                .long("jfr")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .value_name("settings")
+                .help("Record a Java Flight Recording to a timestamped file in the game directory using the settings default (the default), profile or a .jfc file"),
+        )
+        .arg(
+            Arg::with_name("native-memory-tracking")
+                .long("native-memory-tracking")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .possible_values(&["summary", "detail"])
+                .value_name("mode")
+                .help("Track native memory usage of the JVM for use with jcmd VM.native_memory, default summary"),
+        )
+        .arg(
+            Arg::with_name("java-agent")
+                .long("java-agent")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("jar[=options]")
+                .help("Attach a Java agent from the mods directory; may be repeated"),
+        )
+        .arg(
+            Arg::with_name("jvm-opt")
+                .long("jvm-opt")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .value_name("option")
+                .help("An extra option to pass to the JVM, e.g. -Dname=value; may be repeated"),
+        )
+        .arg(
+            Arg::with_name("game-args")
+                .multiple(true)
+                .last(true)
+                .value_name("game args")
+                .help("Arguments to pass to the main method of the game"),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the installation for problems without starting the game"),
+        )
+        .subcommand(
+            SubCommand::with_name("conflicts")
+                .about("Report the files which are supplied by more than one archive on the search path")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the report as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vfs")
+                .about("Inspect the files that the game sees once every archive is mounted")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("ls")
+                        .about("List a directory")
+                        .arg(Arg::with_name("path").help("The directory to list, the root by default")),
+                )
+                .subcommand(
+                    SubCommand::with_name("cat")
+                        .about("Write a file to stdout")
+                        .arg(Arg::with_name("path").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("stat")
+                        .about("Describe a file or directory")
+                        .arg(Arg::with_name("path").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("extract")
+                        .about("Copy a file or directory out of the virtual filesystem")
+                        .arg(Arg::with_name("path").required(true))
+                        .arg(
+                            Arg::with_name("output")
+                                .help("Where to write the file or directory, its own name in the current directory by default"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("which")
+                        .about("Print the archive or directory which supplies a file")
+                        .arg(Arg::with_name("path").required(true)),
+                ),
+        )
+        .get_matches()
+}
 
-    let mut java_arg_bldr = InitArgsBuilder::new()
-        .version(JNIVersion::V8)
-        .option("-Djava.class.path=.;physfs_java.jar;mods;mods/*")
-        .option("-Djava.locale.providers=COMPAT")
-        .option("-XX:+UseShenandoahGC")
-        .option("-XX:+AlwaysPreTouch")
-        .option("-XX:+DisableExplicitGC")
-        .option("-XX:-UseBiasedLocking");
-
-    let heap_size = cli_args.value_of("heap-size").unwrap_or("512m");
-    java_arg_bldr = java_arg_bldr
-        .option(&format!("-Xms{}", heap_size))
-        .option(&format!("-Xmx{}", heap_size));
-
-    if cli_args.is_present("await-debug") {
-        let debug_port = cli_args.value_of("debug-port").unwrap();
-        java_arg_bldr = java_arg_bldr.option(&format!(
-            "-agentlib:jdwp=transport=dt_socket,server=y,suspend=y,address=127.0.0.1:{}",
-            debug_port
-        ));
+fn launch(config: &Config, output_dir: &Path) -> Result<()> {
+    // Two instances of the game writing to the same directory overwrite each other's files.
+    // The lock is taken first, so that a refused launch leaves the running game's log,
+    // launch report and write directory alone
+    let _instance_lock = if config.allow_multiple {
+        None
+    } else {
+        Some(InstanceLock::acquire(output_dir)?)
+    };
+
+    logging::start_launch_log(output_dir);
+
+    let mut report = LaunchReport::new(config.main_class());
+
+    let result = launch_with_report(config, &mut report);
+
+    // The report is written before the game's main method is entered,
+    // so it is only left to write here if the launch failed before then
+    if !report.is_written() {
+        report.error = result.as_ref().err().map(|err| format!("{:#}", err));
+
+        if let Err(report_err) = report.write() {
+            warn!("{:#}", report_err);
+        }
     }
 
-    if cli_args.is_present("jmx-monitoring") {
-        let jmx_port = cli_args.value_of("jmx-port").unwrap();
-        java_arg_bldr = java_arg_bldr
-            .option("-Dcom.sun.management.jmxremote.host=127.0.0.1")
-            .option(&format!("-Dcom.sun.management.jmxremote.port={}", jmx_port))
-            .option(&format!(
-                "-Dcom.sun.management.jmxremote.rmi.port={}",
-                jmx_port
-            ))
-            .option("-Dcom.sun.management.jmxremote.authenticate=false")
-            .option("-Dcom.sun.management.jmxremote.ssl=false");
+    result
+}
+
+fn launch_with_report(config: &Config, report: &mut LaunchReport) -> Result<()> {
+    let physfs = search_path::init(config, Some(report))?;
+
+    report.search_path = physfs::search_path();
+    report.write_dir = physfs::write_dir().map(|write_dir| write_dir.display().to_string());
+
+    debug!("PhysFS search path: {}", report.search_path.join(", "));
+
+    let java_runtime = report.time("Java runtime discovery", || {
+        let java_runtime = jvm::discover(config)?;
+        java_runtime.validate()?;
+        Ok(java_runtime)
+    })?;
+
+    info!("Using {}", java_runtime);
+
+    report.java_runtime = Some(java_runtime.to_string());
+
+    let preset = preset::select(config.jvm.preset, &java_runtime, &config.jvm.options)?;
+
+    report.preset = Some(preset.to_string());
+
+    info!(
+        "Using the {} preset with JVM options {}",
+        preset,
+        preset.jvm_options(config.heap_size()).join(" ")
+    );
+
+    let (jvm_options, unsupported_options) =
+        java_runtime.filter_options(config.jvm_options(preset)?);
+
+    for option in unsupported_options {
+        warn!(
+            "Ignoring JVM option {} which is not supported by {}",
+            option, java_runtime
+        );
     }
 
-    if cli_args.is_present("gc-logging") {
-        java_arg_bldr =
-            java_arg_bldr.option("-Xlog:gc*,age*=debug:file=gc.log::filecount=1,filesize=5M");
+    let class_path = java_class_path(config)?;
+
+    report.class_path = Some(class_path.clone());
+    report.jvm_options = jvm_options.clone();
+
+    let mut java_arg_bldr = InitArgsBuilder::new()
+        .version(JNIVersion::V8)
+        .option(&format!("-Djava.class.path={}", class_path));
+
+    for option in jvm_options {
+        java_arg_bldr = java_arg_bldr.option(&option);
     }
 
     let java_args = java_arg_bldr
         .build()
         .context("Failed to create Java VM args")?;
 
-    // Ugly workaround for inner field of InitArgs being private;
-    // we need it to call the JNI_CreateJavaVM function dynamically
+    // Ugly workaround for the fields of InitArgs being private;
+    // we need them to call the JNI_CreateJavaVM function dynamically
+    // and to add the exit and abort hooks, which aren't string options
     struct VMInitArgs {
         pub inner: sys::JavaVMInitArgs,
         pub opts: Vec<sys::JavaVMOption>,
     }
 
-    let mut raw_java_args: VMInitArgs = unsafe { std::mem::transmute(java_args) };
-
-    let lib = lib::Library::new("./bin/server/jvm.dll").context("Unable to find jvm.dll")?;
-    let mut raw_java_vm: *mut sys::JavaVM = std::ptr::null_mut();
-    let mut raw_env: *mut sys::JNIEnv = std::ptr::null_mut();
-
-    let JNI_CreateJavaVM: lib::Symbol<
-        unsafe extern "C" fn(
-            pvm: *mut *mut sys::JavaVM,
-            penv: *mut *mut sys::JNIEnv,
-            args: *mut sys::JavaVMInitArgs,
-        ) -> sys::jint,
-    > = unsafe {
-        lib.get(b"JNI_CreateJavaVM\0")
-            .context("Unable to find JNI_CreateJavaVM function")?
-    };
+    // transmute checks that the two structs are the same size; the option strings
+    // are leaked, as InitArgs would otherwise free them when it is dropped
+    let raw_java_args: VMInitArgs = unsafe { std::mem::transmute(java_args) };
+
+    let mut vm_options = raw_java_args.opts;
 
-    unsafe {
-        jni_error_code_to_result(JNI_CreateJavaVM(
-            &mut raw_java_vm,
-            &mut raw_env,
-            &mut raw_java_args.inner,
-        ))
-        .context("Error creating Java VM")?
+    vm_options.extend_from_slice(&jvm::hook_options());
+
+    let mut vm_init_args = sys::JavaVMInitArgs {
+        options: vm_options.as_mut_ptr(),
+        nOptions: vm_options.len() as sys::jint,
+        ..raw_java_args.inner
     };
 
-    let java_vm = unsafe { JavaVM::from_raw(raw_java_vm)? };
+    let lib = java_runtime.load_library()?;
+
+    let java_vm = report.time("JVM creation", || {
+        let mut raw_java_vm: *mut sys::JavaVM = std::ptr::null_mut();
+        let mut raw_env: *mut sys::JNIEnv = std::ptr::null_mut();
+
+        let JNI_CreateJavaVM: lib::Symbol<
+            unsafe extern "system" fn(
+                pvm: *mut *mut sys::JavaVM,
+                penv: *mut *mut sys::JNIEnv,
+                args: *mut sys::JavaVMInitArgs,
+            ) -> sys::jint,
+        > = unsafe {
+            lib.get(b"JNI_CreateJavaVM\0")
+                .context("Unable to find JNI_CreateJavaVM function")?
+        };
+
+        unsafe {
+            jni_error_code_to_result(JNI_CreateJavaVM(
+                &mut raw_java_vm,
+                &mut raw_env,
+                &mut vm_init_args,
+            ))
+            .context("Error creating Java VM")?
+        };
+
+        Ok(unsafe { JavaVM::from_raw(raw_java_vm)? })
+    })?;
+
+    // The JVM can't be unloaded once it has been created, even after it is destroyed
+    std::mem::forget(lib);
 
     let attach_guard = java_vm
         .attach_current_thread()
@@ -394,39 +568,207 @@ fn main() -> Result<()> {
 
     let physfs_loader_class = load_class(env, system_loader, "com.maddox.rts.PhysFSLoader")?;
 
-    let physfs_inputstream_class =
-        load_class_from_physfs_jar(env, system_loader, "com.maddox.rts.PhysFSInputStream")?;
-
     // Load physfs_jni.dll in the system loader
-    call_loadnative_method(env, physfs_inputstream_class)?;
+    report.time("PhysFSInputStream._loadNative", || {
+        let physfs_inputstream_class = define_class(
+            env,
+            system_loader,
+            ClassSource::PhysFSJar,
+            "com.maddox.rts.PhysFSInputStream",
+        )?;
+
+        call_loadnative_method(env, physfs_inputstream_class)
+    })?;
 
     // Create PhysFS loader
-    let physfs_loader = call_loader_method(env, physfs_loader_class)?;
-
-    let physfs_reader_class =
-        load_class_from_physfs_jar(env, physfs_loader, "com.maddox.rts.PhysFSReader")?;
+    let physfs_loader = report.time("PhysFSLoader creation", || {
+        call_loader_method(env, physfs_loader_class)
+    })?;
 
     // Load physfs_rts.dll in the PhysFS loader
-    call_loadnative_method(env, physfs_reader_class)?;
-
-    let physfs_class = load_class_from_physfs_jar(env, system_loader, "com.maddox.rts.PhysFS")?;
+    report.time("PhysFSReader._loadNative", || {
+        let physfs_reader_class = define_class(
+            env,
+            physfs_loader,
+            ClassSource::PhysFSJar,
+            "com.maddox.rts.PhysFSReader",
+        )?;
+
+        call_loadnative_method(env, physfs_reader_class)
+    })?;
 
-    // Mount files.zip, ensuring all game classes are on PhysFS search path
-    mount_files_zip(env, physfs_class)?;
+    // Define the PhysFS class which the game uses to mount its archives;
+    // files.zip is already on the search path, mounted by the launcher
+    define_class(
+        env,
+        system_loader,
+        ClassSource::PhysFSJar,
+        "com.maddox.rts.PhysFS",
+    )?;
 
     // Load DT.dll and rts.dll in the PhysFS loader
-    let sfs_inputstream_class =
-        load_class_from_zip(env, physfs_loader, "com.maddox.rts.SFSInputStream")?;
-
-    call_loadnative_method(env, sfs_inputstream_class)?;
+    report.time("SFSInputStream._loadNative", || {
+        let sfs_inputstream_class = define_class(
+            env,
+            physfs_loader,
+            ClassSource::SearchPath,
+            "com.maddox.rts.SFSInputStream",
+        )?;
+
+        call_loadnative_method(env, sfs_inputstream_class)
+    })?;
 
     // Preload game classes by loading com.maddox.il2.game.Main
-    call_preload_method(env, physfs_loader)?;
+    report.time("PhysFSLoader.preload", || {
+        call_preload_method(env, physfs_loader)
+    })?;
 
     // Load the main class of the game
-    let main_class = load_class(env, physfs_loader, "com.maddox.il2.game.GameWin3D")?;
+    let main_class = report.time("Main class loading", || {
+        load_class(env, physfs_loader, config.main_class())
+    })?;
 
-    call_main_method(env, main_class)?;
+    // The main method only returns when the game exits
+    match report.write() {
+        Ok(()) => debug!("Wrote {}", launch_report::LAUNCH_REPORT_FILE),
+        Err(err) => warn!("{:#}", err),
+    }
 
-    Ok(())
+    if let Err(err) = call_main_method(env, main_class, &config.game_args) {
+        // DestroyJavaVM would wait forever for the game's other threads, such as
+        // the one that PhysFS logs missing files from, so the launcher reports the
+        // error and exits instead. PhysFS is left mounted, as those threads still use it
+        std::mem::forget(physfs);
+
+        return Err(err);
+    }
+
+    drop(attach_guard);
+
+    jvm::destroy_java_vm(java_vm)
+}
+
+fn run(cli_args: &ArgMatches<'static>) -> Result<()> {
+    let mut config = Config::load(Path::new(config::CONFIG_FILE))?;
+
+    config.apply_cli_args(cli_args)?;
+
+    let output_dir = search_path::output_dir(&config);
+
+    // The other commands log to the game directory if the write directory can't
+    // be found, so that doctor can still report the problem
+    if !is_launch(cli_args) {
+        match output_dir.as_ref() {
+            Ok(output_dir) => logging::append_to_log(output_dir),
+            Err(err) => {
+                logging::append_to_log(&logging::output_dir());
+                warn!("{:#}", err);
+            }
+        }
+    }
+
+    match cli_args.subcommand() {
+        ("doctor", Some(_)) => {
+            // Doctor may be started from a shortcut, with no console to print to
+            let new_console = console::attach_or_alloc_console();
+            let passed = doctor::run(&config);
+
+            if new_console {
+                console::wait_for_enter();
+            }
+
+            // The failed checks have been printed, so they aren't reported as a launcher error
+            if !passed {
+                log::logger().flush();
+                std::process::exit(1);
+            }
+
+            Ok(())
+        }
+        ("conflicts", Some(conflicts_args)) => {
+            console::attach_parent_console();
+            search_path::init(&config, None).and_then(|_physfs| {
+                let report = conflicts::find_conflicts()?;
+
+                if conflicts_args.is_present("json") {
+                    report.print_json()
+                } else {
+                    report.print_text();
+                    Ok(())
+                }
+            })
+        }
+        ("vfs", Some(vfs_args)) => {
+            console::attach_parent_console();
+            search_path::init(&config, None).and_then(|_physfs| vfs::run(vfs_args))
+        }
+        _ if cli_args.is_present("print-mounts") => {
+            console::attach_parent_console();
+            search_path::init(&config, None).map(|_physfs| search_path::print())
+        }
+        _ => {
+            if config.use_console() {
+                console::attach_or_alloc_console();
+            }
+
+            launch(&config, &output_dir?)
+        }
+    }
+}
+
+/// Record an error that stopped the launcher where the user can find it,
+/// as release builds have no console to print it to
+fn report_error(err: &anyhow::Error) {
+    // A refused launch leaves the files of the game that is running alone
+    if err.is::<AlreadyRunning>() {
+        error!("{:#}", err);
+
+        if !console::has_console() {
+            console::show_error_message("OpenIL2", &format!("{:#}", err));
+        }
+
+        return;
+    }
+
+    error!("{:?}", err);
+
+    logging::flush_pending();
+
+    if let Err(log_err) = exception::write_crash_log(err) {
+        error!("Unable to write {}: {}", exception::CRASH_LOG_FILE, log_err);
+    }
+
+    if let Err(log_err) = logging::write_last_error(err) {
+        error!("Unable to write {}: {}", logging::LAST_ERROR_FILE, log_err);
+    }
+
+    if !console::has_console() {
+        console::show_error_message(
+            "OpenIL2",
+            &format!(
+                "{:#}\n\nThe details have been written to {}.",
+                err,
+                logging::output_path(logging::LAST_ERROR_FILE).display()
+            ),
+        );
+    }
+}
+
+/// Whether the command line launches the game, rather than running one of the other commands
+fn is_launch(cli_args: &ArgMatches<'static>) -> bool {
+    cli_args.subcommand_name().is_none() && !cli_args.is_present("print-mounts")
+}
+
+fn main() {
+    let cli_args = cli_args();
+
+    logging::init(cli_args.value_of("log-level"), is_launch(&cli_args));
+
+    info!("OpenIL2 {}", env!("VERGEN_SEMVER_LIGHTWEIGHT"));
+
+    if let Err(err) = run(&cli_args) {
+        report_error(&err);
+        log::logger().flush();
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,392 @@
+use crate::physfs;
+use anyhow::{bail, Context, Result};
+use log::warn;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub const MODLOAD_FILE: &str = ".modload";
+
+/// A console command from the `.modload` file, which the game evaluates
+/// from its `.rc` file on startup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModloadCommand {
+    /// `sfs MOUNT <archive>`
+    Mount { archive: String },
+    /// `sfs MOUNTAS <archive> <mount point>`
+    MountAs {
+        archive: String,
+        mount_point: String,
+    },
+    /// `sfs UNMOUNT <archive>`
+    Unmount { archive: String },
+}
+
+impl ModloadCommand {
+    pub fn archive(&self) -> &str {
+        match self {
+            ModloadCommand::Mount { archive } => archive,
+            ModloadCommand::MountAs { archive, .. } => archive,
+            ModloadCommand::Unmount { archive } => archive,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModloadEntry {
+    /// The line number of the command, starting from 1
+    pub line: usize,
+    pub command: ModloadCommand,
+}
+
+/// Parse a line of `.modload`, skipping commands other than `sfs` mounts, which
+/// the game evaluates without affecting the search path
+fn parse_line(line_number: usize, line: &str) -> Result<Option<ModloadCommand>> {
+    // Commands prefixed with @ are not echoed to the game console
+    let line = line.trim().trim_start_matches('@');
+
+    if line.is_empty() || line.starts_with("//") {
+        return Ok(None);
+    }
+
+    let words: Vec<&str> = line.split_whitespace().collect();
+
+    if !words[0].eq_ignore_ascii_case("sfs") {
+        warn!(
+            "Ignoring unsupported command {} on line {} of {}",
+            words[0], line_number, MODLOAD_FILE
+        );
+        return Ok(None);
+    }
+
+    let sub_command = match words.get(1) {
+        Some(sub_command) => sub_command.to_ascii_uppercase(),
+        None => bail!("Missing sfs command"),
+    };
+
+    let command = match (sub_command.as_str(), &words[2..]) {
+        ("MOUNT", [archive]) => ModloadCommand::Mount {
+            archive: archive.to_string(),
+        },
+        ("MOUNTAS", [archive, mount_point]) => ModloadCommand::MountAs {
+            archive: archive.to_string(),
+            mount_point: mount_point.to_string(),
+        },
+        ("UNMOUNT", [archive]) => ModloadCommand::Unmount {
+            archive: archive.to_string(),
+        },
+        ("MOUNT", _) | ("UNMOUNT", _) => {
+            bail!("Expected sfs {} <archive>", sub_command)
+        }
+        ("MOUNTAS", _) => bail!("Expected sfs MOUNTAS <archive> <mount point>"),
+        _ => {
+            warn!(
+                "Ignoring unsupported sfs command {} on line {} of {}",
+                sub_command, line_number, MODLOAD_FILE
+            );
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(command))
+}
+
+pub fn parse(contents: &str) -> Result<Vec<ModloadEntry>> {
+    let mut entries = Vec::new();
+
+    for (idx, line) in contents.lines().enumerate() {
+        let command = parse_line(idx + 1, line)
+            .with_context(|| format!("Invalid entry on line {} of {}", idx + 1, MODLOAD_FILE))?;
+
+        if let Some(command) = command {
+            entries.push(ModloadEntry {
+                line: idx + 1,
+                command,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Parse the `.modload` file at `path`, which is treated as empty if it doesn't exist
+pub fn read_modload(path: &Path) -> Result<Vec<ModloadEntry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents =
+        std::fs::read(path).with_context(|| format!("Unable to read {}", path.display()))?;
+
+    // The game reads its console scripts in a single-byte codepage
+    parse(&String::from_utf8_lossy(&contents))
+}
+
+/// The archive that the game mounts for an entry, as `PhysFS.mountArchive`
+/// substitutes the repacked zip file for an SFS file
+pub fn mounted_archive(archive: &str) -> String {
+    let ext_idx = archive.len().saturating_sub(4);
+
+    match archive.get(ext_idx..) {
+        Some(ext) if ext.eq_ignore_ascii_case(".sfs") => format!("{}.zip", &archive[..ext_idx]),
+        _ => archive.to_string(),
+    }
+}
+
+/// The location of an archive, where relative paths are relative to the game directory
+pub fn resolve_archive(archive: &str, game_dir: &Path) -> PathBuf {
+    game_dir.join(mounted_archive(archive).replace("\\", "/"))
+}
+
+// Archives are compared ignoring case, as the game was written for Windows
+fn archive_key(archive: &str) -> String {
+    let archive = mounted_archive(archive).replace("\\", "/");
+    archive.trim_start_matches("./").to_ascii_lowercase()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModloadProblem {
+    Missing {
+        line: usize,
+        archive: String,
+    },
+    Duplicate {
+        line: usize,
+        archive: String,
+        first_line: usize,
+    },
+    NotMounted {
+        line: usize,
+        archive: String,
+    },
+}
+
+impl ModloadProblem {
+    /// Whether the problem would stop the game from starting, rather than
+    /// being ignored by PhysFS
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, ModloadProblem::Duplicate { .. })
+    }
+}
+
+impl fmt::Display for ModloadProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModloadProblem::Missing { line, archive } => {
+                write!(f, "Line {}: {} doesn't exist", line, archive)
+            }
+            ModloadProblem::Duplicate {
+                line,
+                archive,
+                first_line,
+            } => write!(
+                f,
+                "Line {}: {} is already mounted on line {}",
+                line, archive, first_line
+            ),
+            ModloadProblem::NotMounted { line, archive } => {
+                write!(
+                    f,
+                    "Line {}: {} is unmounted but isn't mounted",
+                    line, archive
+                )
+            }
+        }
+    }
+}
+
+/// Find the entries which mount missing archives, mount an archive twice,
+/// or unmount an archive that isn't mounted
+pub fn validate(entries: &[ModloadEntry], game_dir: &Path) -> Vec<ModloadProblem> {
+    let mut problems = Vec::new();
+    let mut mounted: HashMap<String, usize> = HashMap::new();
+
+    for entry in entries {
+        let archive = entry.command.archive();
+        let key = archive_key(archive);
+
+        match entry.command {
+            ModloadCommand::Mount { .. } | ModloadCommand::MountAs { .. } => {
+                if !resolve_archive(archive, game_dir).exists() {
+                    problems.push(ModloadProblem::Missing {
+                        line: entry.line,
+                        archive: mounted_archive(archive),
+                    });
+                }
+
+                match mounted.get(&key) {
+                    Some(first_line) => problems.push(ModloadProblem::Duplicate {
+                        line: entry.line,
+                        archive: archive.to_string(),
+                        first_line: *first_line,
+                    }),
+                    None => {
+                        mounted.insert(key, entry.line);
+                    }
+                }
+            }
+            ModloadCommand::Unmount { .. } => {
+                if mounted.remove(&key).is_none() {
+                    problems.push(ModloadProblem::NotMounted {
+                        line: entry.line,
+                        archive: archive.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+/// Apply the entries to the PhysFS search path in the same way as the game,
+/// which mounts each archive ahead of those already on the search path.
+///
+/// The archives are mounted using the same names as the game, so that PhysFS
+/// ignores them when the game evaluates `.modload` again on startup.
+pub fn apply(entries: &[ModloadEntry]) -> Result<()> {
+    for entry in entries {
+        let archive = mounted_archive(entry.command.archive());
+
+        match &entry.command {
+            ModloadCommand::Mount { .. } => physfs::mount_at(&archive, None, false),
+            ModloadCommand::MountAs { mount_point, .. } => {
+                physfs::mount_at(&archive, Some(mount_point), false)
+            }
+            ModloadCommand::Unmount { .. } => physfs::unmount(&archive),
+        }
+        .with_context(|| format!("Unable to apply line {} of {}", entry.line, MODLOAD_FILE))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mount(archive: &str) -> ModloadCommand {
+        ModloadCommand::Mount {
+            archive: archive.to_string(),
+        }
+    }
+
+    fn unmount(archive: &str) -> ModloadCommand {
+        ModloadCommand::Unmount {
+            archive: archive.to_string(),
+        }
+    }
+
+    fn entries(commands: Vec<ModloadCommand>) -> Vec<ModloadEntry> {
+        commands
+            .into_iter()
+            .enumerate()
+            .map(|(idx, command)| ModloadEntry {
+                line: idx + 1,
+                command,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_sfs_commands() {
+        let modload = "// Mods\n\
+                       \n\
+                       sfs MOUNT MODS/a.sfs\n\
+                       @SFS mountas  MODS/b.zip  3do\n\
+                       sfs Unmount MODS/a.sfs\n";
+
+        assert_eq!(
+            parse(modload).unwrap(),
+            vec![
+                ModloadEntry {
+                    line: 3,
+                    command: mount("MODS/a.sfs"),
+                },
+                ModloadEntry {
+                    line: 4,
+                    command: ModloadCommand::MountAs {
+                        archive: "MODS/b.zip".to_string(),
+                        mount_point: "3do".to_string(),
+                    },
+                },
+                ModloadEntry {
+                    line: 5,
+                    command: unmount("MODS/a.sfs"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_unsupported_commands() {
+        let modload = "fov 90\nsfs LIST\nsfs MOUNT MODS/a.zip\n";
+
+        assert_eq!(
+            parse(modload).unwrap(),
+            vec![ModloadEntry {
+                line: 3,
+                command: mount("MODS/a.zip"),
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_sfs_commands_with_wrong_arguments() {
+        assert!(parse("sfs").is_err());
+        assert!(parse("sfs MOUNT").is_err());
+        assert!(parse("sfs MOUNT a.zip b.zip").is_err());
+        assert!(parse("sfs MOUNTAS a.zip").is_err());
+        assert!(parse("sfs UNMOUNT").is_err());
+    }
+
+    #[test]
+    fn mounts_repacked_zip_for_sfs() {
+        assert_eq!(mounted_archive("MODS/a.sfs"), "MODS/a.zip");
+        assert_eq!(mounted_archive("MODS/A.SFS"), "MODS/A.zip");
+        assert_eq!(mounted_archive("MODS/a.zip"), "MODS/a.zip");
+        assert_eq!(mounted_archive("sfs"), "sfs");
+    }
+
+    #[test]
+    fn finds_modload_problems() {
+        let game_dir = std::env::temp_dir().join(format!("openil2-modload-{}", std::process::id()));
+        std::fs::create_dir_all(game_dir.join("MODS")).unwrap();
+        std::fs::write(game_dir.join("MODS/a.zip"), b"").unwrap();
+
+        let problems = validate(
+            &entries(vec![
+                mount("MODS/a.sfs"),
+                mount("./MODS/a.zip"),
+                mount("MODS/missing.sfs"),
+                unmount("MODS/a.zip"),
+                unmount("MODS/a.zip"),
+            ]),
+            &game_dir,
+        );
+
+        std::fs::remove_dir_all(&game_dir).unwrap();
+
+        assert_eq!(
+            problems,
+            vec![
+                ModloadProblem::Duplicate {
+                    line: 2,
+                    archive: "./MODS/a.zip".to_string(),
+                    first_line: 1,
+                },
+                ModloadProblem::Missing {
+                    line: 3,
+                    archive: "MODS/missing.zip".to_string(),
+                },
+                ModloadProblem::NotMounted {
+                    line: 5,
+                    archive: "MODS/a.zip".to_string(),
+                },
+            ]
+        );
+        assert!(!problems[0].is_fatal());
+        assert!(problems[1].is_fatal());
+        assert!(problems[2].is_fatal());
+    }
+}
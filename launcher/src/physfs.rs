@@ -0,0 +1,410 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::env;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+
+// Only part of the PhysFS API is used by the launcher
+#[allow(dead_code)]
+mod bindings {
+    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+}
+
+use bindings::*;
+
+pub struct PhysFS {}
+
+impl Drop for PhysFS {
+    fn drop(&mut self) {
+        unsafe {
+            // This runs on the way out, even after an error,
+            // so a failure here is only worth a warning
+            if PHYSFS_isInit() > 0 && PHYSFS_deinit() == 0 {
+                log::warn!("Unable to deinitialise PhysFS: {}", last_error());
+            }
+        }
+    }
+}
+
+/// The message for the last PhysFS error on this thread
+pub fn last_error() -> String {
+    unsafe {
+        let error = PHYSFS_getLastErrorCode();
+        let msg = PHYSFS_getErrorByCode(error);
+
+        if msg.is_null() {
+            format!("PhysFS error {}", error)
+        } else {
+            CStr::from_ptr(msg).to_string_lossy().into_owned()
+        }
+    }
+}
+
+fn path_c_string(path: &Path) -> Result<CString> {
+    let path_str = path.to_str().ok_or_else(|| {
+        anyhow!(
+            "Unable to convert {} to a valid UTF-8 string",
+            path.display()
+        )
+    })?;
+
+    CString::new(path_str).with_context(|| format!("Unable to convert {} to a C string", path_str))
+}
+
+pub fn init_physfs() -> Result<PhysFS> {
+    let current_dir = env::current_dir().context("Unable to get current directory")?;
+
+    let current_dir_c_str = path_c_string(&current_dir)?;
+
+    unsafe {
+        if PHYSFS_init(std::ptr::null()) == 0 {
+            bail!("Unable to initialise PhysFS");
+        } else if PHYSFS_setWriteDir(current_dir_c_str.as_ptr()) == 0 {
+            bail!("Unable to set current directory as PhysFS write directory");
+        } else if PHYSFS_addToSearchPath(current_dir_c_str.as_ptr(), 0) == 0 {
+            bail!("Unable to add current directory to PhysFS search path");
+        }
+    }
+
+    Ok(PhysFS {})
+}
+
+pub fn set_write_dir(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Unable to create write directory {}", dir.display()))?;
+
+    let dir_c_str = path_c_string(dir)?;
+
+    unsafe {
+        if PHYSFS_setWriteDir(dir_c_str.as_ptr()) == 0 {
+            bail!(
+                "Unable to set {} as PhysFS write directory: {}",
+                dir.display(),
+                last_error()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn mount_path(path: &Path, append: bool) -> Result<()> {
+    let path_c_str = path_c_string(path)?;
+
+    unsafe {
+        if PHYSFS_mount(path_c_str.as_ptr(), std::ptr::null(), append as i32) == 0 {
+            bail!("Unable to mount {}: {}", path.display(), last_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Add an archive or directory to the start of the PhysFS search path
+pub fn mount_first(path: &Path) -> Result<()> {
+    mount_path(path, false)
+}
+
+/// The directory that the game writes to
+pub fn write_dir() -> Option<PathBuf> {
+    unsafe {
+        let write_dir = PHYSFS_getWriteDir();
+
+        if write_dir.is_null() {
+            None
+        } else {
+            Some(PathBuf::from(
+                CStr::from_ptr(write_dir).to_string_lossy().into_owned(),
+            ))
+        }
+    }
+}
+
+/// The per-user directory that PhysFS recommends for an application,
+/// e.g. `%APPDATA%\<org>\<app>` on Windows, which is created if it doesn't exist
+pub fn pref_dir(org: &str, app: &str) -> Result<PathBuf> {
+    let org_c_str = CString::new(org)?;
+    let app_c_str = CString::new(app)?;
+
+    unsafe {
+        let pref_dir = PHYSFS_getPrefDir(org_c_str.as_ptr(), app_c_str.as_ptr());
+
+        if pref_dir.is_null() {
+            bail!(
+                "Unable to get the user's application directory: {}",
+                last_error()
+            );
+        }
+
+        Ok(PathBuf::from(
+            CStr::from_ptr(pref_dir).to_string_lossy().into_owned(),
+        ))
+    }
+}
+
+/// Add an archive or directory to the PhysFS search path in the same way as
+/// the PhysFS JNI bindings, which use an upper case mount point
+pub fn mount_at(archive: &str, mount_point: Option<&str>, append: bool) -> Result<()> {
+    let archive_c_str = CString::new(archive)
+        .with_context(|| format!("Unable to convert {} to a C string", archive))?;
+
+    let mount_point_c_str = mount_point
+        .map(|mount_point| {
+            CString::new(mount_point.to_ascii_uppercase())
+                .with_context(|| format!("Unable to convert {} to a C string", mount_point))
+        })
+        .transpose()?;
+
+    let mount_point_ptr = mount_point_c_str
+        .as_ref()
+        .map(|mount_point| mount_point.as_ptr())
+        .unwrap_or(std::ptr::null());
+
+    unsafe {
+        if PHYSFS_mount(archive_c_str.as_ptr(), mount_point_ptr, append as i32) == 0 {
+            bail!("Unable to mount {}: {}", archive, last_error());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn unmount(archive: &str) -> Result<()> {
+    let archive_c_str = CString::new(archive)
+        .with_context(|| format!("Unable to convert {} to a C string", archive))?;
+
+    unsafe {
+        if PHYSFS_unmount(archive_c_str.as_ptr()) == 0 {
+            bail!("Unable to unmount {}: {}", archive, last_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy and free a null terminated list of strings returned by PhysFS
+unsafe fn take_list(list: *mut *mut c_char) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut entry = list;
+
+    while !(*entry).is_null() {
+        strings.push(CStr::from_ptr(*entry).to_string_lossy().into_owned());
+        entry = entry.add(1);
+    }
+
+    PHYSFS_freeList(list as *mut c_void);
+
+    strings
+}
+
+/// The archives and directories on the PhysFS search path, in the order they are searched
+pub fn search_path() -> Vec<String> {
+    unsafe {
+        let list = PHYSFS_getSearchPath();
+
+        if list.is_null() {
+            Vec::new()
+        } else {
+            take_list(list)
+        }
+    }
+}
+
+/// The mount point of an archive or directory on the search path, `/` for the root
+pub fn mount_point(archive: &str) -> Option<String> {
+    let archive_c_str = CString::new(archive).ok()?;
+
+    unsafe {
+        let mount_point = PHYSFS_getMountPoint(archive_c_str.as_ptr());
+
+        if mount_point.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(mount_point).to_string_lossy().into_owned())
+        }
+    }
+}
+
+/// Convert a game path to the form used for entries in the repacked zip files,
+/// in the same way as the PhysFS JNI bindings
+pub fn normalise_path(path: &str) -> String {
+    path.replace("\\", "/")
+        .to_ascii_uppercase()
+        .replace(".CLASS", ".class")
+}
+
+fn game_path_c_string(path: &str) -> Result<CString> {
+    CString::new(normalise_path(path))
+        .with_context(|| format!("Unable to convert {} to a C string", path))
+}
+
+fn exact_path_c_string(path: &str) -> Result<CString> {
+    CString::new(path).with_context(|| format!("Unable to convert {} to a C string", path))
+}
+
+pub fn exists(path: &str) -> Result<bool> {
+    let path_c_str = game_path_c_string(path)?;
+
+    unsafe { Ok(PHYSFS_exists(path_c_str.as_ptr()) != 0) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FileStat {
+    pub file_type: FileType,
+    /// The size of the file in bytes, if it is known
+    pub size: Option<u64>,
+    /// The last modification time in seconds since the Unix epoch, if it is known
+    pub modified: Option<i64>,
+    pub read_only: bool,
+}
+
+/// Describe a game path on the search path, or `None` if it doesn't exist
+pub fn stat(path: &str) -> Result<Option<FileStat>> {
+    Ok(stat_c_str(&game_path_c_string(path)?))
+}
+
+/// Describe a path on the search path exactly as it is given
+pub fn stat_exact(path: &str) -> Result<Option<FileStat>> {
+    Ok(stat_c_str(&exact_path_c_string(path)?))
+}
+
+fn stat_c_str(path_c_str: &CStr) -> Option<FileStat> {
+    let stat = unsafe {
+        let mut stat = std::mem::zeroed::<PHYSFS_Stat>();
+
+        if PHYSFS_stat(path_c_str.as_ptr(), &mut stat) == 0 {
+            return None;
+        }
+
+        stat
+    };
+
+    let file_type = match stat.filetype {
+        PHYSFS_FileType_PHYSFS_FILETYPE_REGULAR => FileType::Regular,
+        PHYSFS_FileType_PHYSFS_FILETYPE_DIRECTORY => FileType::Directory,
+        PHYSFS_FileType_PHYSFS_FILETYPE_SYMLINK => FileType::Symlink,
+        _ => FileType::Other,
+    };
+
+    Some(FileStat {
+        file_type,
+        size: if stat.filesize < 0 {
+            None
+        } else {
+            Some(stat.filesize as u64)
+        },
+        modified: if stat.modtime < 0 {
+            None
+        } else {
+            Some(stat.modtime)
+        },
+        read_only: stat.readonly != 0,
+    })
+}
+
+/// The names of the files and directories in a directory on the search path,
+/// merged across every archive which contains it
+pub fn list_dir(path: &str) -> Result<Vec<String>> {
+    list_dir_c_str(path, &game_path_c_string(path)?)
+}
+
+/// The names in a directory on the search path given exactly, as they are stored
+pub fn list_dir_exact(path: &str) -> Result<Vec<String>> {
+    list_dir_c_str(path, &exact_path_c_string(path)?)
+}
+
+fn list_dir_c_str(path: &str, path_c_str: &CStr) -> Result<Vec<String>> {
+    unsafe {
+        let list = PHYSFS_enumerateFiles(path_c_str.as_ptr());
+
+        if list.is_null() {
+            bail!("Unable to list {}: {}", path, last_error());
+        }
+
+        Ok(take_list(list))
+    }
+}
+
+/// The archive or directory on the search path which supplies a game path
+pub fn real_dir(path: &str) -> Result<Option<String>> {
+    Ok(real_dir_c_str(&game_path_c_string(path)?))
+}
+
+/// The archive or directory on the search path which supplies a path given exactly
+pub fn real_dir_exact(path: &str) -> Result<Option<String>> {
+    Ok(real_dir_c_str(&exact_path_c_string(path)?))
+}
+
+fn real_dir_c_str(path_c_str: &CStr) -> Option<String> {
+    unsafe {
+        let real_dir = PHYSFS_getRealDir(path_c_str.as_ptr());
+
+        if real_dir.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(real_dir).to_string_lossy().into_owned())
+        }
+    }
+}
+
+/// A file opened for reading from the search path
+pub struct File {
+    handle: *mut PHYSFS_File,
+}
+
+impl File {
+    /// Open a game path, normalised in the same way as the PhysFS JNI bindings
+    pub fn open(path: &str) -> Result<File> {
+        File::open_c_str(path, game_path_c_string(path)?)
+    }
+
+    /// Open a path on the search path exactly as it is given
+    pub fn open_exact(path: &str) -> Result<File> {
+        File::open_c_str(path, exact_path_c_string(path)?)
+    }
+
+    fn open_c_str(path: &str, path_c_str: CString) -> Result<File> {
+        let handle = unsafe { PHYSFS_openRead(path_c_str.as_ptr()) };
+
+        if handle.is_null() {
+            bail!("Unable to open {}: {}", path, last_error());
+        }
+
+        Ok(File { handle })
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = unsafe {
+            PHYSFS_readBytes(
+                self.handle,
+                buf.as_mut_ptr() as *mut c_void,
+                buf.len() as PHYSFS_uint64,
+            )
+        };
+
+        if read < 0 {
+            Err(io::Error::other(last_error()))
+        } else {
+            Ok(read as usize)
+        }
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe {
+            PHYSFS_close(self.handle);
+        }
+    }
+}
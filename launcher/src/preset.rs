@@ -0,0 +1,198 @@
+use crate::jvm::JavaRuntime;
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::fmt;
+
+/// A named set of garbage collector and heap options
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Shenandoah with a fixed size heap, the launcher's original settings
+    LowLatency,
+    /// ZGC with a fixed size heap
+    Zgc,
+    /// G1, which trades longer pauses for less GC overhead
+    Throughput,
+    /// The serial collector with a heap that grows and shrinks as needed
+    LowMemory,
+}
+
+pub const PRESET_NAMES: [&str; 4] = ["low-latency", "zgc", "throughput", "low-memory"];
+
+pub const DEFAULT_PRESET: Preset = Preset::LowLatency;
+
+// Used when the runtime can't honour the default preset, as G1 is always available
+const FALLBACK_PRESET: Preset = Preset::Throughput;
+
+impl Preset {
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name {
+            "low-latency" => Some(Preset::LowLatency),
+            "zgc" => Some(Preset::Zgc),
+            "throughput" => Some(Preset::Throughput),
+            "low-memory" => Some(Preset::LowMemory),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::LowLatency => "low-latency",
+            Preset::Zgc => "zgc",
+            Preset::Throughput => "throughput",
+            Preset::LowMemory => "low-memory",
+        }
+    }
+
+    /// The option which selects the garbage collector
+    pub fn gc_option(&self) -> &'static str {
+        match self {
+            Preset::LowLatency => "-XX:+UseShenandoahGC",
+            Preset::Zgc => "-XX:+UseZGC",
+            Preset::Throughput => "-XX:+UseG1GC",
+            Preset::LowMemory => "-XX:+UseSerialGC",
+        }
+    }
+
+    pub fn jvm_options(&self, heap_size: &str) -> Vec<String> {
+        let mut options = vec![self.gc_option().to_string()];
+
+        let tuning: &[&str] = match self {
+            Preset::LowLatency => &["-XX:+DisableExplicitGC", "-XX:-UseBiasedLocking"],
+            Preset::Zgc => &["-XX:+DisableExplicitGC"],
+            Preset::Throughput => &["-XX:+DisableExplicitGC", "-XX:+ParallelRefProcEnabled"],
+            Preset::LowMemory => &["-XX:MinHeapFreeRatio=10", "-XX:MaxHeapFreeRatio=30"],
+        };
+
+        options.extend(tuning.iter().map(|option| option.to_string()));
+
+        if *self == Preset::LowMemory {
+            // Let the heap start small instead of committing all of it up front
+            options.push(format!("-Xmx{}", heap_size));
+        } else {
+            options.push("-XX:+AlwaysPreTouch".to_string());
+            options.push(format!("-Xms{}", heap_size));
+            options.push(format!("-Xmx{}", heap_size));
+        }
+
+        options
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+fn is_gc_option(option: &str) -> bool {
+    option.starts_with("-XX:+Use") && option.ends_with("GC")
+}
+
+/// Choose the preset to launch the JVM with, checking that the runtime supports
+/// its garbage collector and that the other JVM options don't select another one.
+///
+/// The default preset falls back to G1 when the runtime doesn't support Shenandoah,
+/// but a preset that was chosen explicitly must be supported.
+pub fn select(
+    configured: Option<Preset>,
+    java_runtime: &JavaRuntime,
+    other_options: &[String],
+) -> Result<Preset> {
+    let preset = match configured {
+        Some(preset) if java_runtime.supports_option(preset.gc_option()) => preset,
+        Some(preset) => bail!(
+            "The {} preset uses {}, which is not supported by {}",
+            preset,
+            preset.gc_option(),
+            java_runtime
+        ),
+        None if java_runtime.supports_option(DEFAULT_PRESET.gc_option()) => DEFAULT_PRESET,
+        None => FALLBACK_PRESET,
+    };
+
+    if let Some(option) = other_options
+        .iter()
+        .find(|option| is_gc_option(option) && *option != preset.gc_option())
+    {
+        bail!(
+            "The JVM option {} conflicts with the {} preset, which uses {}",
+            option,
+            preset,
+            preset.gc_option()
+        );
+    }
+
+    Ok(preset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jvm::{JavaVersion, RuntimeSource};
+    use std::path::PathBuf;
+
+    fn runtime(version: &str) -> JavaRuntime {
+        JavaRuntime {
+            source: RuntimeSource::Bundled,
+            home: PathBuf::from("."),
+            library: PathBuf::from("lib/server/libjvm.so"),
+            version: JavaVersion::parse(version),
+            arch: None,
+            implementor: None,
+        }
+    }
+
+    fn options(options: &[&str]) -> Vec<String> {
+        options.iter().map(|option| option.to_string()).collect()
+    }
+
+    #[test]
+    fn selects_default_preset_when_supported() {
+        assert_eq!(
+            select(None, &runtime("11.0.9"), &[]).unwrap(),
+            Preset::LowLatency
+        );
+    }
+
+    #[test]
+    fn falls_back_to_g1_without_shenandoah() {
+        assert_eq!(
+            select(None, &runtime("11.0.8"), &[]).unwrap(),
+            Preset::Throughput
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_configured_preset() {
+        assert!(select(Some(Preset::LowLatency), &runtime("11.0.8"), &[]).is_err());
+        assert!(select(Some(Preset::Zgc), &runtime("11.0.9"), &[]).is_err());
+        assert_eq!(
+            select(Some(Preset::Zgc), &runtime("17"), &[]).unwrap(),
+            Preset::Zgc
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_gc_option() {
+        let parallel = options(&["-Xss4m", "-XX:+UseParallelGC"]);
+
+        assert!(select(None, &runtime("17"), &parallel).is_err());
+        assert!(select(Some(Preset::Throughput), &runtime("17"), &parallel).is_err());
+    }
+
+    #[test]
+    fn accepts_the_presets_own_gc_option() {
+        let g1 = options(&["-XX:+UseG1GC", "-XX:MaxGCPauseMillis=50"]);
+
+        assert_eq!(
+            select(Some(Preset::Throughput), &runtime("17"), &g1).unwrap(),
+            Preset::Throughput
+        );
+        // The fallback is chosen before the other options are checked
+        assert_eq!(
+            select(None, &runtime("11.0.8"), &g1).unwrap(),
+            Preset::Throughput
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use crate::config::Config;
+use crate::launch_report::LaunchReport;
+use crate::modload::{self, MODLOAD_FILE};
+use crate::physfs::{self, init_physfs, PhysFS};
+use crate::{FILES_ZIP, PHYSFS_JAR};
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Where physfs_java.jar is mounted, so that the launcher can read its classes
+/// through PhysFS without them being seen by the game
+pub const PHYSFS_JAR_MOUNT_POINT: &str = "OPENIL2/BOOTSTRAP";
+
+/// The names that PhysFS uses to find the per-user write directory,
+/// e.g. `%APPDATA%\OpenIL2\openil2` on Windows
+const PREF_DIR_ORG: &str = "OpenIL2";
+const PREF_DIR_APP: &str = "openil2";
+
+/// The per-user write directory used when no profile is selected
+const DEFAULT_PROFILE_DIR: &str = "default";
+
+/// The directory that the game should write to, if not the game directory
+fn write_dir(config: &Config, game_dir: &Path) -> Result<Option<PathBuf>> {
+    if let Some(write_dir) = config.write_dir.as_ref() {
+        Ok(Some(game_dir.join(write_dir)))
+    } else if config.user_write_dir {
+        // Each profile gets its own directory, so that it can be reset on its own
+        let profile_dir = config.profile.as_deref().unwrap_or(DEFAULT_PROFILE_DIR);
+
+        Ok(Some(
+            physfs::pref_dir(PREF_DIR_ORG, PREF_DIR_APP)?.join(profile_dir),
+        ))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The directory that the game will write to, which holds the lock file of a
+/// running game and the launcher's log, launch report and error files, so that
+/// it can be locked and logged to before the search path is built.
+///
+/// PhysFS is only initialised while it finds the per-user directory.
+pub fn output_dir(config: &Config) -> Result<PathBuf> {
+    let game_dir = env::current_dir().context("Unable to get current directory")?;
+    let _physfs = init_physfs()?;
+
+    Ok(write_dir(config, &game_dir)?.unwrap_or(game_dir))
+}
+
+/// Run a phase of initialisation, timing it if there is a launch report
+fn time<T, F>(report: &mut Option<&mut LaunchReport>, name: &'static str, phase: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    match report {
+        Some(report) => report.time(name, phase),
+        None => phase(),
+    }
+}
+
+/// Initialise PhysFS and build the search path that the game expects.
+///
+/// The archives from `.modload` are searched first, as the game mounts each of
+/// them ahead of the others, followed by the mounts from `openil2.toml`,
+/// files.zip and finally the game directory. physfs_java.jar is mounted last,
+/// under its own mount point.
+///
+/// A write directory other than the game directory is searched before all of
+/// them, so that the files the game writes shadow those in the installation.
+///
+/// Each phase is timed in `report` when one is given.
+pub fn init(config: &Config, mut report: Option<&mut LaunchReport>) -> Result<PhysFS> {
+    let game_dir = env::current_dir().context("Unable to get current directory")?;
+
+    let (physfs, write_dir) = time(&mut report, "PhysFS init", || {
+        let physfs = init_physfs()?;
+        let write_dir = write_dir(config, &game_dir)?;
+
+        if let Some(write_dir) = write_dir.as_ref() {
+            physfs::set_write_dir(write_dir)?;
+        }
+
+        Ok((physfs, write_dir))
+    })?;
+
+    // files.zip is searched before the game directory, as it is when the
+    // game mounts it, and the mounts from openil2.toml before files.zip
+    time(&mut report, "Mount files.zip", || {
+        physfs::mount_first(Path::new(FILES_ZIP))
+    })?;
+
+    time(&mut report, "Mount archives", || {
+        mount_archives(config, &game_dir, write_dir.as_deref())
+    })?;
+
+    Ok(physfs)
+}
+
+/// Mount the archives from `openil2.toml` and `.modload`, physfs_java.jar and
+/// the write directory
+fn mount_archives(config: &Config, game_dir: &Path, write_dir: Option<&Path>) -> Result<()> {
+    for mount in config.mounts.iter().rev() {
+        physfs::mount_first(mount)?;
+    }
+    physfs::mount_at(PHYSFS_JAR, Some(PHYSFS_JAR_MOUNT_POINT), true)?;
+
+    let entries = modload::read_modload(Path::new(MODLOAD_FILE))?;
+    let (fatal, warnings): (Vec<_>, Vec<_>) = modload::validate(&entries, game_dir)
+        .into_iter()
+        .partition(|problem| problem.is_fatal());
+
+    for problem in warnings {
+        warn!("Ignoring entry in {}: {}", MODLOAD_FILE, problem);
+    }
+
+    if !fatal.is_empty() {
+        let problems: Vec<String> = fatal.iter().map(|problem| problem.to_string()).collect();
+
+        bail!(
+            "Invalid entries in {}:\n{}",
+            MODLOAD_FILE,
+            problems.join("\n")
+        );
+    }
+
+    modload::apply(&entries)?;
+
+    // The game directory is already on the search path
+    if let Some(write_dir) = write_dir.filter(|write_dir| *write_dir != game_dir) {
+        physfs::mount_first(write_dir)?;
+        info!("Writing to {}", write_dir.display());
+    }
+
+    Ok(())
+}
+
+/// Print the PhysFS search path in the order it is searched
+pub fn print() {
+    if let Some(write_dir) = physfs::write_dir() {
+        println!("Write directory: {}", write_dir.display());
+    }
+
+    for (index, archive) in physfs::search_path().iter().enumerate() {
+        match physfs::mount_point(archive) {
+            Some(mount_point) if mount_point != "/" => {
+                println!("{:>3}. {} at {}", index + 1, archive, mount_point)
+            }
+            _ => println!("{:>3}. {}", index + 1, archive),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A UTC date and time, to the second
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtcDateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl UtcDateTime {
+    pub fn now() -> UtcDateTime {
+        UtcDateTime::from_system_time(SystemTime::now())
+    }
+
+    pub fn from_system_time(time: SystemTime) -> UtcDateTime {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or_default();
+
+        let days = secs.div_euclid(86400);
+        let secs_of_day = secs.rem_euclid(86400) as u32;
+
+        // Convert days since the epoch to a date in the proleptic Gregorian calendar,
+        // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        UtcDateTime {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day % 3600 / 60,
+            second: secs_of_day % 60,
+        }
+    }
+
+    /// A timestamp which can be used in file names, e.g. `20210127-183000`
+    pub fn file_timestamp(&self) -> String {
+        format!(
+            "{:04}{:02}{:02}-{:02}{:02}{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+
+    /// An RFC 3339 timestamp, e.g. `2021-01-27T18:30:00Z`
+    pub fn rfc3339(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> UtcDateTime {
+        UtcDateTime::from_system_time(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn converts_the_epoch() {
+        assert_eq!(at(0).rfc3339(), "1970-01-01T00:00:00Z");
+        assert_eq!(at(0).file_timestamp(), "19700101-000000");
+    }
+
+    #[test]
+    fn converts_leap_days() {
+        assert_eq!(at(1_582_979_696).rfc3339(), "2020-02-29T12:34:56Z");
+        // 2000 is a leap year, as it is divisible by 400
+        assert_eq!(at(951_782_400).rfc3339(), "2000-02-29T00:00:00Z");
+    }
+
+    #[test]
+    fn converts_dates_after_2100() {
+        // 2100 isn't a leap year, so February ends on the 28th
+        assert_eq!(at(4_107_542_399).rfc3339(), "2100-02-28T23:59:59Z");
+        assert_eq!(at(4_107_542_400).rfc3339(), "2100-03-01T00:00:00Z");
+    }
+}
@@ -0,0 +1,175 @@
+use crate::physfs::{self, FileType};
+use crate::time::UtcDateTime;
+use anyhow::{bail, Context, Result};
+use clap::ArgMatches;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+fn join_game_path(dir: &str, name: &str) -> String {
+    let dir = dir.trim_end_matches(['/', '\\']);
+
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn stat_or_not_found(path: &str) -> Result<physfs::FileStat> {
+    match physfs::stat(path)? {
+        Some(stat) => Ok(stat),
+        None => bail!(
+            "{} was not found on the PhysFS search path",
+            physfs::normalise_path(path)
+        ),
+    }
+}
+
+fn ls(path: &str) -> Result<()> {
+    if stat_or_not_found(path)?.file_type != FileType::Directory {
+        bail!("{} is not a directory", physfs::normalise_path(path));
+    }
+
+    let dir = physfs::normalise_path(path);
+
+    // The names are listed as they are stored, which may not be normalised
+    for name in physfs::list_dir(path)? {
+        match physfs::stat_exact(&join_game_path(&dir, &name))? {
+            Some(stat) if stat.file_type == FileType::Directory => {
+                println!("{:>12}  {}/", "<dir>", name)
+            }
+            Some(stat) => println!(
+                "{:>12}  {}",
+                stat.size.map(|size| size.to_string()).unwrap_or_default(),
+                name
+            ),
+            None => println!("{:>12}  {}", "", name),
+        }
+    }
+
+    Ok(())
+}
+
+fn cat(path: &str) -> Result<()> {
+    let mut file = physfs::File::open(path)?;
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    io::copy(&mut file, &mut stdout).with_context(|| format!("Unable to read {}", path))?;
+
+    stdout.flush().context("Unable to write to stdout")
+}
+
+fn stat(path: &str) -> Result<()> {
+    let stat = stat_or_not_found(path)?;
+
+    println!("Path:      {}", physfs::normalise_path(path));
+    println!("Type:      {:?}", stat.file_type);
+
+    if let Some(size) = stat.size {
+        println!("Size:      {} bytes", size);
+    }
+
+    if let Some(modified) = stat.modified {
+        let modified =
+            UtcDateTime::from_system_time(UNIX_EPOCH + Duration::from_secs(modified as u64));
+
+        println!("Modified:  {}", modified.rfc3339());
+    }
+
+    println!("Read only: {}", stat.read_only);
+
+    if let Some(real_dir) = physfs::real_dir(path)? {
+        println!("Archive:   {}", real_dir);
+    }
+
+    Ok(())
+}
+
+fn extract_file(path: &str, output: &Path) -> Result<()> {
+    if let Some(parent) = output
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create directory {}", parent.display()))?;
+    }
+
+    let mut file = physfs::File::open_exact(path)?;
+
+    let mut output_file = std::fs::File::create(output)
+        .with_context(|| format!("Unable to create {}", output.display()))?;
+
+    io::copy(&mut file, &mut output_file)
+        .with_context(|| format!("Unable to extract {} to {}", path, output.display()))?;
+
+    println!("{} -> {}", path, output.display());
+
+    Ok(())
+}
+
+/// Extract a file or directory by the exact name it is stored under
+fn extract_exact(path: &str, output: &Path) -> Result<()> {
+    match physfs::stat_exact(path)? {
+        Some(stat) if stat.file_type == FileType::Directory => {
+            for name in physfs::list_dir_exact(path)? {
+                extract_exact(&join_game_path(path, &name), &output.join(&name))?;
+            }
+
+            Ok(())
+        }
+        _ => extract_file(path, output),
+    }
+}
+
+fn extract(path: &str, output: &Path) -> Result<()> {
+    stat_or_not_found(path)?;
+    extract_exact(&physfs::normalise_path(path), output)
+}
+
+fn which(path: &str) -> Result<()> {
+    match physfs::real_dir(path)? {
+        Some(real_dir) => {
+            println!("{}", real_dir);
+            Ok(())
+        }
+        None => bail!(
+            "{} was not found on the PhysFS search path",
+            physfs::normalise_path(path)
+        ),
+    }
+}
+
+/// Run a `vfs` subcommand against the search path, which must already be set up
+pub fn run(vfs_args: &ArgMatches<'_>) -> Result<()> {
+    match vfs_args.subcommand() {
+        ("ls", Some(args)) => ls(args.value_of("path").unwrap_or("")),
+        ("cat", Some(args)) => cat(args.value_of("path").unwrap()),
+        ("stat", Some(args)) => stat(args.value_of("path").unwrap()),
+        ("extract", Some(args)) => {
+            let path = args.value_of("path").unwrap();
+
+            let output = match args.value_of("output") {
+                Some(output) => PathBuf::from(output),
+                None => {
+                    let name = path
+                        .trim_end_matches(['/', '\\'])
+                        .rsplit(['/', '\\'])
+                        .next()
+                        .unwrap_or_default();
+
+                    if name.is_empty() {
+                        bail!("An output path is required to extract the root directory");
+                    }
+
+                    PathBuf::from(name)
+                }
+            };
+
+            extract(path, &output)
+        }
+        ("which", Some(args)) => which(args.value_of("path").unwrap()),
+        _ => bail!("A vfs subcommand is required"),
+    }
+}
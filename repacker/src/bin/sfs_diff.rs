@@ -0,0 +1,478 @@
+use anyhow::{anyhow, bail, Context, Result};
+use rusqlite::{params, Connection};
+use sfs::diff::{Change, EntrySummary, Listing};
+use sfs::finger::{FingerHasher, HashMode};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The line that the repacker appends to `.RC`, so that the game evaluates `.modload`
+const MODLOAD_RC_LINE: &[u8] = b"@file .modload\n\n";
+
+struct SfsEntry {
+    fingerprint: i64,
+    file_name: String,
+    key_hash: Option<i32>,
+    key_len_offset: Option<i32>,
+    key_idx_offset: Option<i32>,
+}
+
+struct Database {
+    connection: Connection,
+    /// The name of every known fingerprint, from any SFS file
+    names: HashMap<i64, String>,
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Database> {
+        let connection = Connection::open(path)
+            .with_context(|| format!("Unable to connect to SFS database {}", path.display()))?;
+
+        let names = Database::load_names(&connection)?;
+
+        Ok(Database { connection, names })
+    }
+
+    fn load_names(connection: &Connection) -> Result<HashMap<i64, String>> {
+        let mut names_stmt = connection
+            .prepare(
+                "SELECT fingerprint, file_name
+                 FROM sfs_entry
+                 WHERE file_name IS NOT NULL;",
+            )
+            .context("Unable to prepare SFS database query")?;
+
+        let names = names_stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .context("Unable to query SFS database")?
+            .filter_map(|name| name.ok())
+            .collect();
+
+        Ok(names)
+    }
+
+    fn file_name(&self, fingerprint: i64) -> Option<String> {
+        self.names.get(&fingerprint).cloned()
+    }
+
+    fn entries(&self, sfs_file_name: &str) -> Result<HashMap<i64, SfsEntry>> {
+        let mut entries_stmt = self
+            .connection
+            .prepare(
+                "SELECT fingerprint, file_name, key_hash, key_len_offset, key_idx_offset
+                 FROM sfs_entry
+                 WHERE file_name IS NOT NULL
+                 AND sfs_file = ?1 COLLATE NOCASE;",
+            )
+            .context("Unable to prepare SFS database query")?;
+
+        let entries = entries_stmt
+            .query_map(params![sfs_file_name], |row| {
+                Ok(SfsEntry {
+                    fingerprint: row.get(0)?,
+                    file_name: row.get(1)?,
+                    key_hash: row.get(2)?,
+                    key_len_offset: row.get(3)?,
+                    key_idx_offset: row.get(4)?,
+                })
+            })
+            .context("Unable to query SFS database")?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| (entry.fingerprint, entry))
+            .collect();
+
+        Ok(entries)
+    }
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("zip"))
+        .unwrap_or(false)
+}
+
+fn file_name_of(path: &Path) -> Result<String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_string())
+        .ok_or_else(|| anyhow!("Unable to get file name for {}", path.display()))
+}
+
+/// The name of the SFS file that a repacked zip was created from
+fn sfs_file_name_of(path: &Path) -> Result<String> {
+    let file_stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| {
+            anyhow!(
+                "Unable to get filename without extension for {}",
+                path.display()
+            )
+        })?;
+
+    Ok(format!("{}.SFS", file_stem))
+}
+
+/// The name given to an SFS entry when it is repacked into a zip file
+fn repacked_name(file_name: &str) -> String {
+    file_name.to_ascii_uppercase().replace(".CLASS", ".class")
+}
+
+/// How the entries of an SFS file are expected to differ from its repacked zip
+struct Repacking<'a> {
+    /// Run classes through the class transformer, as the repacker does,
+    /// so that they can be compared with the zip
+    class_transformer: Option<&'a Path>,
+}
+
+/// Run a class through the class transformer in the same way as the repacker
+fn transform_class(class_transformer: &Path, class_data: &[u8]) -> Result<Vec<u8>> {
+    let mut process = Command::new(class_transformer)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .current_dir(class_transformer.parent().unwrap_or_else(|| Path::new(".")))
+        .spawn()
+        .with_context(|| format!("Unable to run {}", class_transformer.display()))?;
+
+    let mut stdin = process
+        .stdin
+        .take()
+        .context("Unable to write to the class transformer")?;
+
+    let mut stdout = process
+        .stdout
+        .take()
+        .context("Unable to read from the class transformer")?;
+
+    // Write on another thread so that a large class can't fill both pipes
+    let class_data = class_data.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&class_data));
+
+    let mut transformed_data = Vec::new();
+
+    stdout
+        .read_to_end(&mut transformed_data)
+        .context("Unable to read from the class transformer")?;
+
+    writer
+        .join()
+        .map_err(|_| anyhow!("Unable to write to the class transformer"))?
+        .context("Unable to write to the class transformer")?;
+
+    let status = process
+        .wait()
+        .context("Unable to wait for the class transformer")?;
+
+    if !status.success() {
+        bail!("The class transformer failed with {}", status);
+    }
+
+    Ok(transformed_data)
+}
+
+/// Decrypt an SFS entry and apply the same rewrites as the repacker, if any
+fn sfs_entry_data(
+    db_entry: &SfsEntry,
+    raw_data: &[u8],
+    repacking: Option<&Repacking<'_>>,
+) -> Result<Vec<u8>> {
+    if let Some(class_name) = db_entry.file_name.strip_suffix(".class") {
+        let class_data = sfs::decrypt_class(
+            sfs::class_hash(&class_name.replace("/", ".")),
+            raw_data.to_vec(),
+        );
+
+        return match repacking.and_then(|repacking| repacking.class_transformer) {
+            Some(class_transformer) => transform_class(class_transformer, &class_data),
+            None => Ok(class_data),
+        };
+    }
+
+    let mut data = raw_data.to_vec();
+
+    if let (Some(hash), Some(len_offset), Some(idx_offset)) = (
+        db_entry.key_hash,
+        db_entry.key_len_offset,
+        db_entry.key_idx_offset,
+    ) {
+        sfs::decrypt_data(&mut data, hash, len_offset, idx_offset);
+    }
+
+    if repacking.is_some() && repacked_name(&db_entry.file_name) == ".RC" {
+        data.extend_from_slice(MODLOAD_RC_LINE);
+    }
+
+    Ok(data)
+}
+
+/// An entry that couldn't be read, which is reported without stopping the diff
+struct EntryError {
+    fingerprint: i64,
+    name: String,
+    error: anyhow::Error,
+}
+
+fn sfs_listing(
+    path: &Path,
+    db: Option<&Database>,
+    repacking: Option<&Repacking<'_>>,
+    errors: &mut Vec<EntryError>,
+) -> Result<Listing> {
+    let file_name = file_name_of(path)?;
+
+    let sfs_file = sfs::read_sfs(path)
+        .with_context(|| format!("Unable to read SFS file {}", path.display()))?;
+
+    let decompressed = sfs::decompress_sfs(&sfs_file)
+        .with_context(|| format!("Unable to decompress SFS file {}", path.display()))?;
+
+    let db = match db {
+        Some(db) => db,
+        None => return Ok(sfs::diff::sfs_listing(&sfs_file, &decompressed, |_| None)),
+    };
+
+    let db_entries = db.entries(&file_name)?;
+
+    let mut listing = Listing::new();
+
+    for item in sfs_file.toc.iter() {
+        let raw_data = sfs::entry_data(&decompressed, item);
+
+        let summary = match db_entries.get(&item.fingerprint) {
+            Some(db_entry) => match sfs_entry_data(db_entry, raw_data, repacking) {
+                Ok(data) => {
+                    EntrySummary::new(item.fingerprint, Some(db_entry.file_name.clone()), &data)
+                }
+                Err(error) => {
+                    errors.push(EntryError {
+                        fingerprint: item.fingerprint,
+                        name: db_entry.file_name.clone(),
+                        error: error.context(format!(
+                            "Unable to read {} from {}",
+                            db_entry.file_name, file_name
+                        )),
+                    });
+                    continue;
+                }
+            },
+            None => EntrySummary::new(item.fingerprint, db.file_name(item.fingerprint), raw_data),
+        };
+
+        listing.insert(item.fingerprint, summary);
+    }
+
+    Ok(listing)
+}
+
+fn zip_listing(path: &Path, db: Option<&Database>) -> Result<Listing> {
+    // Zip entry names are uppercased by the repacker, so class names can only
+    // be mapped back to their fingerprints using the database
+    let known_fingerprints: HashMap<String, i64> = match db {
+        Some(db) => db
+            .entries(&sfs_file_name_of(path)?)?
+            .values()
+            .map(|entry| (repacked_name(&entry.file_name), entry.fingerprint))
+            .collect(),
+        None => HashMap::new(),
+    };
+
+    let zip_file =
+        File::open(path).with_context(|| format!("Unable to open file {}", path.display()))?;
+
+    let mut zip_archive = zip::ZipArchive::new(zip_file)
+        .with_context(|| format!("Unable to read zip file {}", path.display()))?;
+
+    let mut listing = Listing::new();
+
+    for idx in 0..zip_archive.len() {
+        let mut zip_entry = zip_archive
+            .by_index(idx)
+            .with_context(|| format!("Unable to read entry #{} in {}", idx, path.display()))?;
+
+        let entry_name = zip_entry.name().trim_end_matches('/').to_string();
+
+        let fingerprint = known_fingerprints
+            .get(&entry_name)
+            .copied()
+            .unwrap_or_else(|| sfs::finger::string(0, &entry_name));
+
+        // Hash the entry while decompressing it rather than reading it all into memory
+        let mut hasher = FingerHasher::new(HashMode::Bytes);
+
+        let size = io::copy(&mut zip_entry, &mut hasher).with_context(|| {
+            format!("Unable to read entry {} in {}", entry_name, path.display())
+        })?;
+
+        listing.insert(
+            fingerprint,
+            EntrySummary {
+                fingerprint,
+                name: Some(entry_name),
+                size: size as usize,
+                hash: hasher.fingerprint(),
+            },
+        );
+    }
+
+    Ok(listing)
+}
+
+fn listing(
+    path: &Path,
+    db: Option<&Database>,
+    repacking: Option<&Repacking<'_>>,
+    errors: &mut Vec<EntryError>,
+) -> Result<Listing> {
+    if is_zip(path) {
+        zip_listing(path, db)
+    } else {
+        sfs_listing(path, db, repacking, errors)
+    }
+}
+
+/// Whether a change is only the class transformer's rewrite of a class,
+/// which can't be checked without running it
+fn is_transformed_class(change: &Change) -> bool {
+    match change {
+        Change::Modified { new, .. } => new
+            .name
+            .as_ref()
+            .map(|name| name.ends_with(".class"))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn print_change(change: &Change) {
+    match change {
+        Change::Added(entry) => println!(
+            "A {:016X} {:>10} {:016X} {}",
+            entry.fingerprint,
+            entry.size,
+            entry.hash,
+            entry.display_name()
+        ),
+        Change::Removed(entry) => println!(
+            "D {:016X} {:>10} {:016X} {}",
+            entry.fingerprint,
+            entry.size,
+            entry.hash,
+            entry.display_name()
+        ),
+        Change::Modified { old, new } => println!(
+            "M {:016X} {:>10} {:016X} {} (was {} bytes, {:016X})",
+            new.fingerprint,
+            new.size,
+            new.hash,
+            new.name.clone().unwrap_or_else(|| old.display_name()),
+            old.size,
+            old.hash
+        ),
+    }
+}
+
+const USAGE: &str =
+    "Usage: sfs_diff [--class-transformer <exe>] <old archive> <new archive> [SFS database]";
+
+fn main() -> Result<()> {
+    let mut class_transformer = None;
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        if arg == "--class-transformer" {
+            class_transformer = Some(PathBuf::from(args.next().context(USAGE)?));
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+
+    if paths.len() < 2 || paths.len() > 3 {
+        bail!(USAGE);
+    }
+
+    let old_path = paths[0].clone();
+    let new_path = paths[1].clone();
+
+    let sfs_db = match paths.get(2) {
+        Some(sfs_db) => Some(sfs_db.clone()),
+        None => old_path
+            .parent()
+            .map(|dir| dir.join("sfs_db.sqlite"))
+            .filter(|sfs_db| sfs_db.is_file()),
+    };
+
+    let db = sfs_db.as_deref().map(Database::open).transpose()?;
+
+    // An SFS file compared with a zip is expected to have been repacked
+    let repacking = if is_zip(&old_path) != is_zip(&new_path) {
+        Some(Repacking {
+            class_transformer: class_transformer.as_deref(),
+        })
+    } else {
+        None
+    };
+
+    let mut errors = Vec::new();
+
+    let old_listing = listing(&old_path, db.as_ref(), repacking.as_ref(), &mut errors)?;
+    let new_listing = listing(&new_path, db.as_ref(), repacking.as_ref(), &mut errors)?;
+
+    // Entries that couldn't be read would otherwise show up as added or removed
+    let unreadable: HashSet<i64> = errors.iter().map(|error| error.fingerprint).collect();
+
+    let (transformed, changes): (Vec<Change>, Vec<Change>) =
+        sfs::diff::diff_listings(&old_listing, &new_listing)
+            .into_iter()
+            .filter(|change| !unreadable.contains(&change.fingerprint()))
+            .partition(|change| {
+                repacking
+                    .as_ref()
+                    .map(|repacking| repacking.class_transformer.is_none())
+                    .unwrap_or(false)
+                    && is_transformed_class(change)
+            });
+
+    for change in changes.iter() {
+        print_change(change);
+    }
+
+    for error in errors.iter() {
+        println!(
+            "E {:016X} {}: {:#}",
+            error.fingerprint, error.name, error.error
+        );
+    }
+
+    let count = |pred: fn(&Change) -> bool| changes.iter().filter(|c| pred(c)).count();
+
+    let unchanged = old_listing
+        .iter()
+        .filter(
+            |(fingerprint, old_entry)| match new_listing.get(fingerprint) {
+                Some(new_entry) => !old_entry.differs_from(new_entry),
+                None => false,
+            },
+        )
+        .count();
+
+    println!(
+        "{} added, {} removed, {} modified, {} unchanged, {} unreadable",
+        count(|c| matches!(c, Change::Added(_))),
+        count(|c| matches!(c, Change::Removed(_))),
+        count(|c| matches!(c, Change::Modified { .. })),
+        unchanged,
+        unreadable.len()
+    );
+
+    if !transformed.is_empty() {
+        println!(
+            "{} classes differ as the repacker rewrites them, use --class-transformer to compare them",
+            transformed.len()
+        );
+    }
+
+    Ok(())
+}
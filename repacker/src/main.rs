@@ -113,7 +113,7 @@ fn unpack_sfs(
                     .to_ascii_uppercase()
                     .replace(".CLASS", ".class");
 
-                if sfs_entry.file_size == 0 && !entry_name.contains(".") {
+                if sfs::is_directory_entry(&entry_name, sfs_entry.file_size as u32) {
                     zip.add_directory(&entry_name, zip_options)
                         .with_context(|| {
                             anyhow!(
@@ -164,7 +164,9 @@ fn unpack_sfs(
                             sfs_entry.key_idx_offset,
                         ) {
                             (Some(hash), Some(len_offset), Some(idx_offset)) => {
-                                sfs::decrypt_data(raw_data, hash, len_offset, idx_offset)
+                                let mut data = raw_data;
+                                sfs::decrypt_data(&mut data, hash, len_offset, idx_offset);
+                                data
                             }
                             _ => raw_data,
                         }
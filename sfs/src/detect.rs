@@ -0,0 +1,323 @@
+use crate::CLASS_MAGIC;
+use std::convert::TryInto;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    JavaClass,
+    Dds,
+    Tga,
+    Bmp,
+    Wav,
+    Ogg,
+    /// A mesh in the game's text format, which starts with a `[Common]` or `[LOD]` section.
+    ///
+    /// Binary meshes have no signature that is known to be reliable,
+    /// so they are not detected and are classified as `Unknown`.
+    Mesh,
+    Material,
+    Mission,
+    Ini,
+    Text,
+    Zip,
+    Sfs,
+    Unknown,
+}
+
+impl ContentType {
+    /// The extension, always in lower case, given to entries of this type
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContentType::JavaClass => "class",
+            ContentType::Dds => "dds",
+            ContentType::Tga => "tga",
+            ContentType::Bmp => "bmp",
+            ContentType::Wav => "wav",
+            ContentType::Ogg => "ogg",
+            ContentType::Mesh => "msh",
+            ContentType::Material => "mat",
+            ContentType::Mission => "mis",
+            ContentType::Ini => "ini",
+            ContentType::Text => "txt",
+            ContentType::Zip => "zip",
+            ContentType::Sfs => "sfs",
+            ContentType::Unknown => "dat",
+        }
+    }
+
+    pub fn is_known(&self) -> bool {
+        *self != ContentType::Unknown
+    }
+}
+
+// Only the start of a text file is inspected to decide whether it is text
+const TEXT_SAMPLE_LEN: usize = 4096;
+
+const TGA_FOOTER: &[u8] = b"TRUEVISION-XFILE.\0";
+
+fn le_u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn le_u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn is_bmp(data: &[u8]) -> bool {
+    // The file size in the header must agree with the size of the entry
+    data.starts_with(b"BM") && le_u32_at(data, 2) == Some(data.len() as u32)
+}
+
+fn is_wav(data: &[u8]) -> bool {
+    data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE")
+}
+
+fn is_tga(data: &[u8]) -> bool {
+    if data.ends_with(TGA_FOOTER) {
+        return true;
+    }
+
+    // Version 1 TGA files have no signature, so check the header fields
+    // for values that are plausible for the images shipped with the game
+    if data.len() < 18 {
+        return false;
+    }
+
+    let id_len = data[0] as u64;
+    let colour_map_type = data[1];
+    let image_type = data[2];
+    // Sizes are computed in u64, as 65535 x 65535 x 4 bytes overflows a 32-bit usize
+    let colour_map_len = le_u16_at(data, 5).unwrap() as u64;
+    let colour_map_depth = data[7] as u64;
+    let width = le_u16_at(data, 12).unwrap() as u64;
+    let height = le_u16_at(data, 14).unwrap() as u64;
+    let pixel_depth = data[16] as u64;
+
+    let valid_header = colour_map_type <= 1
+        && matches!(image_type, 1 | 2 | 3 | 9 | 10 | 11)
+        && matches!(pixel_depth, 8 | 15 | 16 | 24 | 32)
+        && width > 0
+        && height > 0;
+
+    if !valid_header {
+        return false;
+    }
+
+    let colour_map_size = colour_map_len * colour_map_depth.div_ceil(8);
+    let header_size = 18 + id_len + colour_map_size;
+    let data_len = data.len() as u64;
+
+    if image_type >= 9 {
+        // Run-length encoded images can't be checked against the pixel size
+        data_len > header_size
+    } else {
+        width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(pixel_depth.div_ceil(8)))
+            .and_then(|pixels_size| pixels_size.checked_add(header_size))
+            .map(|image_size| data_len >= image_size)
+            .unwrap_or(false)
+    }
+}
+
+fn is_text(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(TEXT_SAMPLE_LEN)];
+
+    // The game's text files are in single-byte codepages,
+    // so only reject control characters other than whitespace
+    !sample.is_empty()
+        && sample
+            .iter()
+            .all(|b| *b >= 0x20 || matches!(*b, b'\t' | b'\n' | b'\r' | 0x1A))
+}
+
+fn first_section(data: &[u8]) -> Option<String> {
+    let sample = &data[..data.len().min(TEXT_SAMPLE_LEN)];
+
+    String::from_utf8_lossy(sample)
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with("//") && !line.starts_with(';'))
+        .filter(|line| line.starts_with('[') && line.ends_with(']'))
+        .map(|line| line[1..line.len() - 1].trim().to_ascii_uppercase())
+}
+
+fn detect_text(data: &[u8]) -> ContentType {
+    match first_section(data).as_deref() {
+        Some("COMMON") | Some("LOD") | Some("LODS") => ContentType::Mesh,
+        Some("CLASSINFO") => ContentType::Material,
+        Some("MAIN") => ContentType::Mission,
+        Some(_) => ContentType::Ini,
+        None => ContentType::Text,
+    }
+}
+
+/// Classify the contents of an SFS entry using its magic number or,
+/// failing that, the structure of its header
+pub fn detect(data: &[u8]) -> ContentType {
+    if data.starts_with(&CLASS_MAGIC) {
+        ContentType::JavaClass
+    } else if data.starts_with(b"DDS ") {
+        ContentType::Dds
+    } else if is_wav(data) {
+        ContentType::Wav
+    } else if data.starts_with(b"OggS") {
+        ContentType::Ogg
+    } else if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        ContentType::Zip
+    } else if data.starts_with(b"SFS\0") {
+        ContentType::Sfs
+    } else if is_bmp(data) {
+        ContentType::Bmp
+    } else if is_text(data) {
+        detect_text(data)
+    } else if is_tga(data) {
+        ContentType::Tga
+    } else {
+        ContentType::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 1 TGA header, which has no signature, for an uncompressed image
+    fn tga_header(image_type: u8, width: u16, height: u16, pixel_depth: u8) -> Vec<u8> {
+        let mut header = vec![0u8; 18];
+        header[2] = image_type;
+        header[12..14].copy_from_slice(&width.to_le_bytes());
+        header[14..16].copy_from_slice(&height.to_le_bytes());
+        header[16] = pixel_depth;
+        header
+    }
+
+    #[test]
+    fn detects_magic_numbers() {
+        assert_eq!(
+            detect(&[0xCA, 0xFE, 0xBA, 0xBE, 0, 0, 0, 0x2F]),
+            ContentType::JavaClass
+        );
+        assert_eq!(detect(b"DDS |\0\0\0"), ContentType::Dds);
+        assert_eq!(detect(b"RIFF\x24\0\0\0WAVEfmt "), ContentType::Wav);
+        assert_eq!(detect(b"OggS\0\x02"), ContentType::Ogg);
+        assert_eq!(detect(b"PK\x03\x04\x14\0"), ContentType::Zip);
+        assert_eq!(detect(b"PK\x05\x06\0\0"), ContentType::Zip);
+        assert_eq!(detect(b"SFS\0\x01\0"), ContentType::Sfs);
+    }
+
+    #[test]
+    fn rejects_riff_files_other_than_wav() {
+        assert_eq!(detect(b"RIFF\x24\0\0\0AVI LIST"), ContentType::Unknown);
+    }
+
+    #[test]
+    fn detects_bmp_with_matching_size() {
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&10u32.to_le_bytes());
+        bmp.extend_from_slice(&[0, 0, 0, 0]);
+
+        assert_eq!(detect(&bmp), ContentType::Bmp);
+
+        bmp.push(0);
+
+        assert_eq!(detect(&bmp), ContentType::Unknown);
+    }
+
+    #[test]
+    fn detects_tga_by_footer() {
+        let mut tga = vec![0u8; 4];
+        tga.extend_from_slice(TGA_FOOTER);
+
+        assert_eq!(detect(&tga), ContentType::Tga);
+    }
+
+    #[test]
+    fn detects_version_1_tga_by_header() {
+        let mut tga = tga_header(2, 2, 2, 24);
+        tga.extend_from_slice(&[0u8; 2 * 2 * 3]);
+
+        assert_eq!(detect(&tga), ContentType::Tga);
+    }
+
+    #[test]
+    fn detects_version_1_tga_with_id_and_colour_map() {
+        let mut tga = tga_header(1, 2, 1, 8);
+        tga[0] = 3;
+        tga[1] = 1;
+        tga[5..7].copy_from_slice(&4u16.to_le_bytes());
+        tga[7] = 24;
+        tga.extend_from_slice(&[0u8; 3 + 4 * 3 + 2]);
+
+        assert_eq!(detect(&tga), ContentType::Tga);
+
+        // One pixel short
+        tga.pop();
+
+        assert_eq!(detect(&tga), ContentType::Unknown);
+    }
+
+    #[test]
+    fn detects_run_length_encoded_version_1_tga() {
+        let mut tga = tga_header(10, 64, 64, 32);
+        tga.extend_from_slice(&[0xFF, 0, 0, 0, 0]);
+
+        assert_eq!(detect(&tga), ContentType::Tga);
+        assert_eq!(detect(&tga[..18]), ContentType::Unknown);
+    }
+
+    #[test]
+    fn rejects_implausible_tga_headers() {
+        let mut truncated = tga_header(2, 2, 2, 24);
+        truncated.extend_from_slice(&[0u8; 11]);
+        assert_eq!(detect(&truncated), ContentType::Unknown);
+
+        let mut bad_depth = tga_header(2, 2, 2, 12);
+        bad_depth.extend_from_slice(&[0u8; 12]);
+        assert_eq!(detect(&bad_depth), ContentType::Unknown);
+
+        let mut bad_type = tga_header(4, 2, 2, 24);
+        bad_type.extend_from_slice(&[0u8; 12]);
+        assert_eq!(detect(&bad_type), ContentType::Unknown);
+
+        let mut no_width = tga_header(2, 0, 2, 24);
+        no_width.extend_from_slice(&[0u8; 12]);
+        assert_eq!(detect(&no_width), ContentType::Unknown);
+
+        // The pixel size of the largest image doesn't fit in a 32-bit usize
+        let mut huge = tga_header(2, u16::MAX, u16::MAX, 32);
+        huge.extend_from_slice(&[0u8; 12]);
+        assert_eq!(detect(&huge), ContentType::Unknown);
+    }
+
+    #[test]
+    fn detects_text_formats_by_first_section() {
+        assert_eq!(detect(b"[Common]\r\nNumBones 0\r\n"), ContentType::Mesh);
+        assert_eq!(detect(b"[LOD]\n"), ContentType::Mesh);
+        assert_eq!(detect(b"[LODs]\n"), ContentType::Mesh);
+        assert_eq!(
+            detect(b"[ClassInfo]\n  ClassName TMaterial\n"),
+            ContentType::Material
+        );
+        assert_eq!(
+            detect(b"// A mission\n\n[MAIN]\n  MAP Net1Summer/load.ini\n"),
+            ContentType::Mission
+        );
+        assert_eq!(detect(b"; Sound settings\n[sound]\n"), ContentType::Ini);
+        assert_eq!(detect(b"Hello\tworld\r\n\x1A"), ContentType::Text);
+    }
+
+    #[test]
+    fn does_not_detect_empty_or_binary_data() {
+        assert_eq!(detect(b""), ContentType::Unknown);
+        assert_eq!(detect(&[0, 1, 2, 3]), ContentType::Unknown);
+    }
+
+    #[test]
+    fn extensions_are_lower_case() {
+        assert_eq!(ContentType::JavaClass.extension(), "class");
+        assert_eq!(ContentType::Dds.extension(), "dds");
+        assert_eq!(ContentType::Unknown.extension(), "dat");
+    }
+}
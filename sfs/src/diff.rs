@@ -0,0 +1,95 @@
+use crate::{entry_data, finger, SfsFile};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntrySummary {
+    pub fingerprint: i64,
+    pub name: Option<String>,
+    pub size: usize,
+    pub hash: i64,
+}
+
+impl EntrySummary {
+    pub fn new(fingerprint: i64, name: Option<String>, data: &[u8]) -> EntrySummary {
+        EntrySummary {
+            fingerprint,
+            name,
+            size: data.len(),
+            hash: finger::bytes(0, data),
+        }
+    }
+
+    pub fn differs_from(&self, other: &EntrySummary) -> bool {
+        self.size != other.size || self.hash != other.hash
+    }
+
+    pub fn display_name(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("{:X}", self.fingerprint))
+    }
+}
+
+/// The entries of an archive, keyed by the fingerprint of their path
+pub type Listing = BTreeMap<i64, EntrySummary>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(EntrySummary),
+    Removed(EntrySummary),
+    Modified {
+        old: EntrySummary,
+        new: EntrySummary,
+    },
+}
+
+impl Change {
+    pub fn fingerprint(&self) -> i64 {
+        match self {
+            Change::Added(entry) => entry.fingerprint,
+            Change::Removed(entry) => entry.fingerprint,
+            Change::Modified { new, .. } => new.fingerprint,
+        }
+    }
+}
+
+pub fn diff_listings(old: &Listing, new: &Listing) -> Vec<Change> {
+    let removed = old
+        .iter()
+        .filter(|(fingerprint, _)| !new.contains_key(fingerprint))
+        .map(|(_, entry)| Change::Removed(entry.clone()));
+
+    let added_or_modified =
+        new.iter()
+            .filter_map(|(fingerprint, new_entry)| match old.get(fingerprint) {
+                None => Some(Change::Added(new_entry.clone())),
+                Some(old_entry) if old_entry.differs_from(new_entry) => Some(Change::Modified {
+                    old: old_entry.clone(),
+                    new: new_entry.clone(),
+                }),
+                Some(_) => None,
+            });
+
+    let mut changes: Vec<Change> = removed.chain(added_or_modified).collect();
+
+    changes.sort_by_key(|change| change.fingerprint());
+
+    changes
+}
+
+/// Summarise every entry in the table of contents of an SFS file, using
+/// `resolve_name` to recover the original path of each fingerprint
+pub fn sfs_listing<F>(sfs_file: &SfsFile, decompressed: &[u8], resolve_name: F) -> Listing
+where
+    F: Fn(i64) -> Option<String>,
+{
+    sfs_file
+        .toc
+        .iter()
+        .map(|item| {
+            let data = entry_data(decompressed, item);
+            let summary = EntrySummary::new(item.fingerprint, resolve_name(item.fingerprint), data);
+            (item.fingerprint, summary)
+        })
+        .collect()
+}
@@ -1,3 +1,6 @@
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io;
+
 pub static BOTTOM_TABLE: [u32; 256] = [
     0x00000000, 0x23788D5E, 0x46F11ABC, 0x658997E2, 0x0DE23578, 0x2E9AB826, 0x4B132FC4, 0x686BA29A,
     0x38BCE7AE, 0x1BC46AF0, 0x7E4DFD12, 0x5D35704C, 0x355ED2D6, 0x16265F88, 0x73AFC86A, 0x50D74534,
@@ -157,11 +160,196 @@ pub fn key_table(input_hash: i32, len_offset: i32) -> Vec<u8> {
     let tbl: Vec<u8> = (0..table_len)
         .map(|idx| {
             let table_idx = start_offset + idx as usize;
-            return KEY_TABLE[table_idx % KEY_TABLE.len()];
+            KEY_TABLE[table_idx % KEY_TABLE.len()]
         })
         .collect();
 
-    return tbl;
+    tbl
+}
+
+/// Convert a character of a path in the same way as the game before it is hashed
+fn normalise_path_char(c: u32) -> u32 {
+    // Convert alphabetic characters to uppercase
+    if c > 96 && c < 123 {
+        c & 223
+    // Replace forward slash with backslash
+    } else if c == 47 {
+        92
+    } else {
+        c
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashMode {
+    /// Hash raw bytes, as `bytes` does
+    Bytes,
+    /// Hash a path, ignoring case and treating `/` as `\`, as `string` does
+    Path,
+    /// Hash the bytes of 16-bit Java chars into a 32-bit hash, as `inc_int` does
+    Chars,
+}
+
+/// An incremental version of the game's fingerprint hash, which can be fed
+/// through `std::hash::Hasher` or `std::io::Write`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FingerHasher {
+    mode: HashMode,
+    bottom: u32,
+    top: u32,
+}
+
+impl FingerHasher {
+    pub fn new(mode: HashMode) -> FingerHasher {
+        FingerHasher::with_hash(mode, 0)
+    }
+
+    pub fn with_hash(mode: HashMode, hash: i64) -> FingerHasher {
+        // Extract the bottom and top bytes separately
+        FingerHasher {
+            mode,
+            bottom: (hash & 0xFFFFFFFF) as u32,
+            top: (hash >> 32 & 0xFFFFFFFF) as u32,
+        }
+    }
+
+    fn push(&mut self, unit: u32) {
+        let (b, t) = (self.bottom, self.top);
+        self.bottom = ((b << 8) | unit) ^ BOTTOM_TABLE[(b >> 24) as usize];
+
+        // The 32-bit hash of Java chars only uses the bottom table
+        if self.mode != HashMode::Chars {
+            self.top = ((t << 8) | unit) ^ TOP_TABLE[(t >> 24) as usize];
+        }
+    }
+
+    fn push_path_char(&mut self, c: u32) {
+        self.push(normalise_path_char(c));
+    }
+
+    /// Hash a buffer of bytes; in `Path` mode each byte is treated as a
+    /// character, which matches `string` for paths in single-byte encodings
+    pub fn update(&mut self, buf: &[u8]) {
+        for byte in buf {
+            match self.mode {
+                HashMode::Path => self.push_path_char(*byte as u32),
+                _ => self.push(*byte as u32),
+            }
+        }
+    }
+
+    pub fn update_str(&mut self, s: &str) {
+        match self.mode {
+            HashMode::Bytes => self.update(s.as_bytes()),
+            HashMode::Path => s.chars().for_each(|c| self.push_path_char(c as u32)),
+            HashMode::Chars => s.encode_utf16().for_each(|c| self.update(&c.to_le_bytes())),
+        }
+    }
+
+    /// Hash Java chars stored as `i32` values, low byte first
+    pub fn update_chars(&mut self, chars: &[i32]) {
+        for c in chars {
+            let next = *c as u32;
+            self.update(&[(next & 0xFF) as u8, (next >> 8 & 0xFF) as u8]);
+        }
+    }
+
+    pub fn fingerprint(&self) -> i64 {
+        // Combine back together
+        (self.bottom as i64) & 0xFFFFFFFF | (self.top as i64) << 32
+    }
+
+    pub fn int(&self) -> i32 {
+        self.bottom as i32
+    }
+}
+
+impl Hasher for FingerHasher {
+    fn finish(&self) -> u64 {
+        match self.mode {
+            HashMode::Chars => self.bottom as u64,
+            _ => self.fingerprint() as u64,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    /// In `Path` mode this hashes a single character, which is how `GamePath`
+    /// hashes itself, rather than the four bytes of the integer
+    fn write_u32(&mut self, i: u32) {
+        match self.mode {
+            HashMode::Path => self.push_path_char(i),
+            _ => self.update(&i.to_ne_bytes()),
+        }
+    }
+}
+
+impl io::Write for FingerHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A path used as a map key, which is equal to the paths that the game treats
+/// as the same file, ignoring case and treating `/` as `\`.
+///
+/// Hashed by a `FingerHasher` in `Path` mode, its hash is the game's fingerprint.
+#[derive(Debug, Clone)]
+pub struct GamePath(pub String);
+
+impl GamePath {
+    pub fn fingerprint(&self) -> i64 {
+        string(0, &self.0)
+    }
+
+    fn normalised_chars(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.chars().map(|c| normalise_path_char(c as u32))
+    }
+}
+
+impl PartialEq for GamePath {
+    fn eq(&self, other: &GamePath) -> bool {
+        self.normalised_chars().eq(other.normalised_chars())
+    }
+}
+
+impl Eq for GamePath {}
+
+impl Hash for GamePath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for c in self.normalised_chars() {
+            state.write_u32(c);
+        }
+    }
+}
+
+/// Builds `FingerHasher`s for `HashMap`s, by default using the `Path` mode.
+///
+/// Only `GamePath` keys are hashed to the game's fingerprint. The `Hash`
+/// implementations of other keys, such as `str` and `Path`, add their own
+/// framing, so their hashes are not fingerprints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildFingerHasher(pub HashMode);
+
+impl Default for BuildFingerHasher {
+    fn default() -> BuildFingerHasher {
+        BuildFingerHasher(HashMode::Path)
+    }
+}
+
+impl BuildHasher for BuildFingerHasher {
+    type Hasher = FingerHasher;
+
+    fn build_hasher(&self) -> FingerHasher {
+        FingerHasher::new(self.0)
+    }
 }
 
 pub fn int(buf: &[i32]) -> i32 {
@@ -169,63 +357,103 @@ pub fn int(buf: &[i32]) -> i32 {
 }
 
 pub fn inc_int(init: i32, buf: &[i32]) -> i32 {
-    buf.iter().fold(init, |h, n| {
-        let hash = h as u32;
-        let next = *n as u32;
-        let hash = ((hash << 8) | (next & 0xFF)) ^ BOTTOM_TABLE[(hash >> 24) as usize];
-        let hash = ((hash << 8) | (next >> 8 & 0xFF)) ^ BOTTOM_TABLE[(hash >> 24) as usize];
-        return hash as i32;
-    })
+    let mut hasher = FingerHasher::with_hash(HashMode::Chars, init as u32 as i64);
+    hasher.update_chars(buf);
+    hasher.int()
 }
 
 pub fn bytes(hash: i64, buf: &[u8]) -> i64 {
-    // Extract the bottom and top bytes separately
-    let bottom = (hash & 0xFFFFFFFF) as u32;
-    let top = (hash >> 32 & 0xFFFFFFFF) as u32;
+    let mut hasher = FingerHasher::with_hash(HashMode::Bytes, hash);
+    hasher.update(buf);
+    hasher.fingerprint()
+}
 
-    // Update the hash using the input
-    let (new_bottom, new_top) = buf.iter().fold((bottom, top), |(b, t), byte| {
-        let bt = *byte as u32;
-        let new_bottom = ((b << 8) | bt) ^ BOTTOM_TABLE[(b >> 24) as usize];
-        let new_top = ((t << 8) | bt) ^ TOP_TABLE[(t >> 24) as usize];
-        (new_bottom, new_top)
-    });
+pub fn string(hash: i64, name: &str) -> i64 {
+    let mut hasher = FingerHasher::with_hash(HashMode::Path, hash);
+    hasher.update_str(name);
+    hasher.fingerprint()
+}
 
-    // Convert back to u64
-    let bot_64 = new_bottom as i64;
-    let top_64 = new_top as i64;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
 
-    // Combine back together
-    return bot_64 & 0xFFFFFFFF | top_64 << 32;
-}
+    // Pinned from the one-shot implementations which the repacker has used
+    // to find the game's files in its SFS archives
+    const HIER_FINGERPRINT: i64 = 0xBA2F374396AF76C1u64 as i64;
+    const PROPERTIES_FINGERPRINT: i64 = 0x9B6B2DBD7657D3CFu64 as i64;
+    const MAIN_CLASS_HASH: i32 = 0x828AB654u32 as i32;
+    const BYTES_FINGERPRINT: i64 = 0xFA4BCB5B5FE3037Fu64 as i64;
 
-pub fn string(hash: i64, name: &str) -> i64 {
-    // Extract the bottom and top bytes separately
-    let bottom = (hash & 0xFFFFFFFF) as u32;
-    let top = (hash >> 32 & 0xFFFFFFFF) as u32;
-
-    // Update the hash using the input
-    let (new_bottom, new_top) = name.chars().fold((bottom, top), |(b, t), c| {
-        let mut chr = c as u32;
-
-        // Convert alphabetic characters to uppercase
-        if chr > 96 && chr < 123 {
-            chr &= 223;
-        // Replace forward slash with backslash
-        } else if chr == 47 {
-            chr = 92
-        };
-
-        let new_bottom = ((b << 8) | chr) ^ BOTTOM_TABLE[(b >> 24) as usize];
-        let new_top = ((t << 8) | chr) ^ TOP_TABLE[(t >> 24) as usize];
-
-        (new_bottom, new_top)
-    });
-
-    // Convert back to u64
-    let bot_64 = new_bottom as i64;
-    let top_64 = new_top as i64;
-
-    // Combine back together
-    return bot_64 & 0xFFFFFFFF | top_64 << 32;
+    #[test]
+    fn matches_pinned_fingerprints() {
+        assert_eq!(string(0, "3do/plane/Bf-109F-4/hier.him"), HIER_FINGERPRINT);
+        assert_eq!(
+            string(0, "3DO\\PLANE\\BF-109F-4\\HIER.HIM"),
+            HIER_FINGERPRINT
+        );
+        assert_eq!(string(0, "i18n/plane.properties"), PROPERTIES_FINGERPRINT);
+        assert_eq!(bytes(0, b"CAFEBABE"), BYTES_FINGERPRINT);
+
+        let chars: Vec<i32> = "sdwcom.maddox.il2.game.Maincwc2w9e"
+            .chars()
+            .map(|c| c as i32)
+            .collect();
+
+        assert_eq!(int(&chars), MAIN_CLASS_HASH);
+    }
+
+    #[test]
+    fn hashes_incrementally() {
+        let mut hasher = FingerHasher::new(HashMode::Path);
+        hasher.update_str("3do/plane/");
+        hasher.update_str("Bf-109F-4/hier.him");
+        assert_eq!(hasher.fingerprint(), HIER_FINGERPRINT);
+
+        let mut hasher = FingerHasher::new(HashMode::Bytes);
+        io::copy(&mut &b"CAFEBABE"[..], &mut hasher).unwrap();
+        assert_eq!(hasher.fingerprint(), BYTES_FINGERPRINT);
+
+        let mut hasher = FingerHasher::new(HashMode::Chars);
+        hasher.update_str("sdwcom.maddox.");
+        hasher.update_str("il2.game.Maincwc2w9e");
+        assert_eq!(hasher.int(), MAIN_CLASS_HASH);
+    }
+
+    #[test]
+    fn game_path_hashes_to_its_fingerprint() {
+        let path = GamePath("3do/plane/Bf-109F-4/hier.him".to_string());
+
+        assert_eq!(path.fingerprint(), HIER_FINGERPRINT);
+        assert_eq!(
+            BuildFingerHasher::default().hash_one(&path),
+            HIER_FINGERPRINT as u64
+        );
+    }
+
+    #[test]
+    fn game_paths_differing_in_case_and_separators_are_equal() {
+        let mut paths = HashMap::with_hasher(BuildFingerHasher::default());
+
+        paths.insert(GamePath("3do/plane/Bf-109F-4/hier.him".to_string()), 1);
+
+        assert_eq!(
+            paths.get(&GamePath("3DO\\PLANE\\BF-109F-4\\HIER.HIM".to_string())),
+            Some(&1)
+        );
+        assert_eq!(
+            paths.get(&GamePath("3do/plane/Bf-109F-4/hier.msh".to_string())),
+            None
+        );
+
+        let mut std_paths = HashMap::new();
+
+        std_paths.insert(GamePath("i18n/plane.properties".to_string()), 2);
+
+        assert_eq!(
+            std_paths.get(&GamePath("I18N\\PLANE.PROPERTIES".to_string())),
+            Some(&2)
+        );
+    }
 }
@@ -0,0 +1,277 @@
+use crate::finger;
+use std::convert::TryInto;
+
+const WORD_LEN: usize = std::mem::size_of::<u64>();
+
+/// A repeating XOR key, precomputed so that it can be applied to any slice
+/// of an encrypted buffer without allocating or recomputing the key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyStream {
+    // One period of the key followed by enough of its start that a whole
+    // word can be read from any position within the period
+    expanded: Vec<u8>,
+    period: usize,
+}
+
+impl KeyStream {
+    pub fn from_period(key: &[u8]) -> KeyStream {
+        assert!(!key.is_empty(), "The key must not be empty");
+
+        let expanded = key
+            .iter()
+            .cycle()
+            .take(key.len() + WORD_LEN)
+            .cloned()
+            .collect();
+
+        KeyStream {
+            expanded,
+            period: key.len(),
+        }
+    }
+
+    /// The key used for the SFS header and table of contents.
+    ///
+    /// The game picks the key byte for position `idx` using the index
+    /// `idx + idx / 3`, which repeats every 6 bytes.
+    pub fn header(hash: i64) -> KeyStream {
+        let hash_bytes = hash.to_le_bytes();
+        let key: Vec<u8> = (0..6).map(|idx| hash_bytes[(idx + idx / 3) & 7]).collect();
+        KeyStream::from_period(&key)
+    }
+
+    /// The key used for the SFS chunk table.
+    ///
+    /// The game picks the key byte for position `idx` using the index
+    /// `idx + idx / 5`, which repeats every 20 bytes.
+    pub fn chunk_table(hash: i64) -> KeyStream {
+        let hash_bytes = hash.to_le_bytes();
+        let key: Vec<u8> = (0..20).map(|idx| hash_bytes[(idx + idx / 5) & 7]).collect();
+        KeyStream::from_period(&key)
+    }
+
+    /// The key used for encrypted entries and classes
+    pub fn data(key_hash: i32, key_len_offset: i32) -> KeyStream {
+        KeyStream::from_period(&finger::key_table(key_hash, key_len_offset))
+    }
+
+    pub fn period(&self) -> usize {
+        self.period
+    }
+
+    /// XOR `buf` in place with the key, where `offset` is the position of
+    /// the start of `buf` within the encrypted data
+    pub fn apply(&self, buf: &mut [u8], offset: usize) {
+        let mut phase = offset % self.period;
+
+        let mut words = buf.chunks_exact_mut(WORD_LEN);
+
+        for word in words.by_ref() {
+            let key = &self.expanded[phase..phase + WORD_LEN];
+            let key_word = u64::from_ne_bytes(key.try_into().unwrap());
+            let data_word = u64::from_ne_bytes((&*word).try_into().unwrap());
+            word.copy_from_slice(&(data_word ^ key_word).to_ne_bytes());
+            phase = (phase + WORD_LEN) % self.period;
+        }
+
+        for (byte, key) in words
+            .into_remainder()
+            .iter_mut()
+            .zip(&self.expanded[phase..])
+        {
+            *byte ^= key;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decrypt_data, sfs_decrypt, sfs_decrypt2};
+
+    const HASHES: [i64; 4] = [
+        0x0123_4567_89AB_CDEF,
+        -0x0123_4567_89AB_CDEF,
+        0x7FFF_FFFF_FFFF_FFFF,
+        -1,
+    ];
+
+    const KEY_HASHES: [i32; 5] = [0, 1, 0x1234_5678, -0x1234_5678, i32::MAX];
+
+    const KEY_LEN_OFFSETS: [i32; 4] = [0, 14, -3, -40];
+
+    /// Enough bytes to cover several words, with every length in between
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|idx| (idx * 37 + 11) as u8).collect()
+    }
+
+    /// The byte-wise header decryption that `KeyStream::header` replaced
+    fn reference_sfs_decrypt(hash: i64, buf: &[u8]) -> Vec<u8> {
+        let hash_bytes = hash.to_le_bytes();
+
+        buf.iter()
+            .enumerate()
+            .map(|(idx, byte)| {
+                let hash_idx = ((idx as u64 * 0x2AAAAAAAB) >> 0x21) & 7;
+                byte ^ hash_bytes[hash_idx as usize]
+            })
+            .collect()
+    }
+
+    /// The byte-wise chunk table decryption that `KeyStream::chunk_table` replaced
+    fn reference_sfs_decrypt2(hash: i64, buf: &[u8]) -> Vec<u8> {
+        let hash_bytes = hash.to_le_bytes();
+
+        buf.iter()
+            .enumerate()
+            .map(|(idx, byte)| {
+                let hash_idx = ((idx as u64 * 0x4CCCCCCCD) >> 0x22) & 7;
+                byte ^ hash_bytes[hash_idx as usize]
+            })
+            .collect()
+    }
+
+    /// The byte-wise entry decryption that `KeyStream::data` replaced.
+    ///
+    /// It indexed the key table with `(idx + key_idx_offset as usize) % len`,
+    /// which wraps negative offsets through `usize` and so only gives
+    /// a meaningful index for offsets that aren't negative. The index is
+    /// taken modulo the table length here instead, as `decrypt_data` does.
+    fn reference_decrypt_data(
+        buf: &[u8],
+        key_hash: i32,
+        key_len_offset: i32,
+        key_idx_offset: i32,
+    ) -> Vec<u8> {
+        let xor_table = finger::key_table(key_hash, key_len_offset);
+
+        buf.iter()
+            .enumerate()
+            .map(|(idx, byte)| {
+                let table_idx = if key_idx_offset >= 0 {
+                    (idx + key_idx_offset as usize) % xor_table.len()
+                } else {
+                    (idx as i64 + key_idx_offset as i64).rem_euclid(xor_table.len() as i64) as usize
+                };
+
+                xor_table[table_idx] ^ byte
+            })
+            .collect()
+    }
+
+    #[test]
+    fn header_matches_byte_wise_decryption() {
+        for hash in HASHES.iter() {
+            for len in 0..=70 {
+                let mut data = test_data(len);
+                let expected = reference_sfs_decrypt(*hash, &data);
+
+                sfs_decrypt(*hash, &mut data);
+
+                assert_eq!(data, expected, "hash {:X}, length {}", hash, len);
+            }
+        }
+    }
+
+    #[test]
+    fn chunk_table_matches_byte_wise_decryption() {
+        for hash in HASHES.iter() {
+            for len in 0..=70 {
+                let mut data = test_data(len);
+                let expected = reference_sfs_decrypt2(*hash, &data);
+
+                sfs_decrypt2(*hash, &mut data);
+
+                assert_eq!(data, expected, "hash {:X}, length {}", hash, len);
+            }
+        }
+    }
+
+    #[test]
+    fn data_matches_byte_wise_decryption() {
+        let idx_offsets = [0, 1, 7, 8, 13, 1000, i32::MAX];
+
+        for key_hash in KEY_HASHES.iter() {
+            for key_len_offset in KEY_LEN_OFFSETS.iter() {
+                for key_idx_offset in idx_offsets.iter() {
+                    for len in 0..=40 {
+                        let mut data = test_data(len);
+                        let expected = reference_decrypt_data(
+                            &data,
+                            *key_hash,
+                            *key_len_offset,
+                            *key_idx_offset,
+                        );
+
+                        decrypt_data(&mut data, *key_hash, *key_len_offset, *key_idx_offset);
+
+                        assert_eq!(
+                            data, expected,
+                            "key {} {} {}, length {}",
+                            key_hash, key_len_offset, key_idx_offset, len
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn data_with_negative_offset_wraps_within_the_key() {
+        let idx_offsets = [-1, -7, -8, -13, -1000, i32::MIN];
+
+        for key_hash in KEY_HASHES.iter() {
+            for key_len_offset in KEY_LEN_OFFSETS.iter() {
+                for key_idx_offset in idx_offsets.iter() {
+                    for len in 0..=40 {
+                        let mut data = test_data(len);
+                        let expected = reference_decrypt_data(
+                            &data,
+                            *key_hash,
+                            *key_len_offset,
+                            *key_idx_offset,
+                        );
+
+                        decrypt_data(&mut data, *key_hash, *key_len_offset, *key_idx_offset);
+
+                        assert_eq!(
+                            data, expected,
+                            "key {} {} {}, length {}",
+                            key_hash, key_len_offset, key_idx_offset, len
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn applying_to_slices_matches_the_whole_buffer() {
+        let key_stream = KeyStream::data(0x1234_5678, 14);
+        let data = test_data(100);
+
+        let mut whole = data.clone();
+        key_stream.apply(&mut whole, 5);
+
+        for start in 0..data.len() {
+            for end in start..=data.len().min(start + 19) {
+                let mut slice = data[start..end].to_vec();
+                key_stream.apply(&mut slice, 5 + start);
+
+                assert_eq!(slice, &whole[start..end], "slice {}..{}", start, end);
+            }
+        }
+    }
+
+    #[test]
+    fn applying_twice_restores_the_data() {
+        let key_stream = KeyStream::chunk_table(HASHES[0]);
+        let data = test_data(67);
+
+        let mut round_trip = data.clone();
+        key_stream.apply(&mut round_trip, 3);
+        key_stream.apply(&mut round_trip, 3);
+
+        assert_eq!(round_trip, data);
+    }
+}
@@ -1,16 +1,22 @@
+pub mod detect;
+pub mod diff;
 pub mod finger;
+pub mod keystream;
 use anyhow::{anyhow, bail, Context, Result};
 use flate2::bufread::*;
 use indicatif::{ProgressBar, ProgressStyle};
+use keystream::KeyStream;
 use memmap::Mmap;
 use nom::bytes::complete::*;
 use nom::multi::*;
 use nom::number::complete::*;
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::convert::TryInto;
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Debug)]
 pub struct SfsFile {
@@ -109,40 +115,14 @@ pub fn parse_toc<'a>(
     many_m_n(toc_count, toc_count, parse_toc_item)(input)
 }
 
-pub fn sfs_decrypt(hash: i64, buf: &[u8]) -> Vec<u8> {
-    let hash_bytes = hash.to_le_bytes();
-
-    let new_buf = buf
-        .iter()
-        .enumerate()
-        .map(|(idx, byte)| {
-            // I think this a method of computing division using bitshifts, see:
-            //   https://stackoverflow.com/a/436535
-            let idx_64 = idx as u64;
-            let hash_idx = (idx_64 * 0x2AAAAAAAB >> 0x21) & 7;
-            return byte ^ hash_bytes[hash_idx as usize];
-        })
-        .collect();
-
-    return new_buf;
+/// Decrypt the SFS header or table of contents in place
+pub fn sfs_decrypt(hash: i64, buf: &mut [u8]) {
+    KeyStream::header(hash).apply(buf, 0);
 }
 
-pub fn sfs_decrypt2(hash: i64, buf: &[u8]) -> Vec<u8> {
-    let hash_bytes = hash.to_le_bytes();
-
-    let new_buf = buf
-        .iter()
-        .enumerate()
-        .map(|(idx, byte)| {
-            // I think this a method of computing division using bitshifts, see:
-            //   https://stackoverflow.com/a/436535
-            let idx_64 = idx as u64;
-            let hash_idx = (idx_64 * 0x4CCCCCCCD >> 0x22) & 7;
-            return byte ^ hash_bytes[hash_idx as usize];
-        })
-        .collect();
-
-    return new_buf;
+/// Decrypt the SFS chunk table in place
+pub fn sfs_decrypt2(hash: i64, buf: &mut [u8]) {
+    KeyStream::chunk_table(hash).apply(buf, 0);
 }
 
 pub fn read_sfs(path: &Path) -> Result<SfsFile> {
@@ -165,21 +145,12 @@ pub fn read_sfs(path: &Path) -> Result<SfsFile> {
     };
 
     // On version 202 (0xCA) we have to do some additional decryption
-    let decrypted_vec = if header.version == 0xCA {
-        let decrypted = sfs_decrypt(header_hash, &mmap[8..32]);
+    let mut decrypted_vec = header_slice.to_vec();
 
+    if header.version == 0xCA {
         // Decrypt all of the u32 fields from checksum onwards
-        let decrypted_vec: Vec<u8> = mmap[0..8]
-            .iter()
-            .chain(&decrypted)
-            .chain(&mmap[32..256])
-            .map(|b| *b)
-            .collect();
-
-        decrypted_vec
-    } else {
-        header_slice.to_vec()
-    };
+        sfs_decrypt(header_hash, &mut decrypted_vec[8..32]);
+    }
 
     let (_, decrypted_header) = parse_header(&decrypted_vec).unwrap();
     let header_end = decrypted_header.header_end as usize;
@@ -203,8 +174,8 @@ pub fn read_sfs(path: &Path) -> Result<SfsFile> {
 
     // Read the table of contents
     let toc_end = decrypted_header.toc_end as usize;
-    let toc_slice = &mmap[header_end..toc_end];
-    let decrypted_toc = sfs_decrypt(header_hash, toc_slice);
+    let mut decrypted_toc = mmap[header_end..toc_end].to_vec();
+    sfs_decrypt(header_hash, &mut decrypted_toc);
     let (_, mut toc) = parse_toc(&decrypted_header, &decrypted_toc[..]).unwrap();
 
     toc.sort_by(|a, b| a.index.cmp(&b.index));
@@ -226,9 +197,8 @@ pub fn read_sfs(path: &Path) -> Result<SfsFile> {
         toc_end + bytes_to_read
     };
 
-    let chunk_slice = &mmap[toc_end..chunk_table_end];
-
-    let decrypted_table = sfs_decrypt2(header_hash, chunk_slice);
+    let mut decrypted_table = mmap[toc_end..chunk_table_end].to_vec();
+    sfs_decrypt2(header_hash, &mut decrypted_table);
 
     let chunk_offsets: Vec<u32> = decrypted_table
         .chunks(4)
@@ -283,26 +253,14 @@ pub fn decompress_chunk(sfs_file: &SfsFile, boundary: (usize, usize)) -> Vec<u8>
     }
 }
 
-pub fn decrypt_data(
-    raw_data: Vec<u8>,
-    key_hash: i32,
-    key_len_offset: i32,
-    key_idx_offset: i32,
-) -> Vec<u8> {
-    let xor_table = finger::key_table(key_hash, key_len_offset);
-
-    let decrypted_data = raw_data
-        .iter()
-        .enumerate()
-        .map(|(i, b)| {
-            let table_idx = (i + key_idx_offset as usize) % xor_table.len();
-            let xor_val = xor_table[table_idx];
-            let result = xor_val ^ b;
-            return result;
-        })
-        .collect();
-
-    decrypted_data
+/// Decrypt an encrypted entry or class in place.
+///
+/// When decrypting many entries with the same key, build a `KeyStream` once
+/// with `KeyStream::data` and apply it to each buffer instead.
+pub fn decrypt_data(buf: &mut [u8], key_hash: i32, key_len_offset: i32, key_idx_offset: i32) {
+    let key_stream = KeyStream::data(key_hash, key_len_offset);
+    let offset = (key_idx_offset as i64).rem_euclid(key_stream.period() as i64);
+    key_stream.apply(buf, offset as usize);
 }
 
 pub fn unpack_from_sfs_by_fingerprint(
@@ -318,11 +276,7 @@ pub fn unpack_from_sfs_by_fingerprint(
         })
         .context("Couldn't find a matching entry in the SFS file")?;
 
-    let start_offset = toc_item.offset as usize;
-    let end_offset = (toc_item.offset + toc_item.size) as usize;
-    let data = &decompressed[start_offset..end_offset];
-
-    Ok(data.to_vec())
+    Ok(entry_data(decompressed, toc_item).to_vec())
 }
 
 pub fn unpack_from_sfs_by_path(
@@ -343,30 +297,47 @@ pub fn unpack_from_sfs_by_path(
 
 const CLASS_MAGIC: [u8; 4] = [0xCA, 0xFE, 0xBA, 0xBE];
 
+/// The hash of a fully qualified class name, used both to locate the class
+/// in the `cod/` directory and as the key for decrypting its contents
+pub fn class_hash(class_name: &str) -> i32 {
+    let obfuscated_name = format!("sdw{}cwc2w9e", class_name);
+    let obfuscated_chars: Vec<i32> = obfuscated_name.chars().map(|c| c as i32).collect();
+    finger::int(&obfuscated_chars)
+}
+
+pub fn class_fingerprint(class_name: &str) -> i64 {
+    finger::string(0, &format!("cod/{}", class_hash(class_name)))
+}
+
+pub fn entry_data<'a>(decompressed: &'a [u8], entry: &SfsTocItem) -> &'a [u8] {
+    let start_offset = entry.offset as usize;
+    let end_offset = start_offset + entry.size as usize;
+    &decompressed[start_offset..end_offset]
+}
+
 pub fn unpack_from_sfs_by_class_name(
     sfs_file: &SfsFile,
     decompressed: &Vec<u8>,
     class_name: String,
 ) -> Result<Vec<u8>> {
-    let obfuscated_name = format!("sdw{}cwc2w9e", class_name);
-    let obfuscated_chars: Vec<i32> = obfuscated_name.chars().map(|c| return c as i32).collect();
-    let class_hash = finger::int(&obfuscated_chars);
-    let class_fingerprint = finger::string(0, &format!("cod/{}", class_hash));
+    let class_hash = class_hash(&class_name);
+    let class_fingerprint = class_fingerprint(&class_name);
     let raw_class_data = unpack_from_sfs_by_fingerprint(sfs_file, decompressed, class_fingerprint)?;
 
+    Ok(decrypt_class(class_hash, raw_class_data))
+}
+
+/// Decrypt class data stored in the `cod/` directory, restoring the class
+/// file header that is stripped from encrypted classes
+pub fn decrypt_class(class_hash: i32, raw_class_data: Vec<u8>) -> Vec<u8> {
     if raw_class_data.starts_with(&CLASS_MAGIC) {
-        Ok(raw_class_data)
+        raw_class_data
     } else {
-        let decrypted_class_data: Vec<u8> = decrypt_data(raw_class_data, class_hash, 14, 0);
-
-        let decrypted_class_data_with_header: Vec<u8> =
-            [0xCA, 0xFE, 0xBA, 0xBE, 0x00, 0x00, 0x00, 0x2F]
-                .iter()
-                .cloned()
-                .chain(decrypted_class_data)
-                .collect();
-
-        Ok(decrypted_class_data_with_header)
+        let mut class_data = Vec::with_capacity(raw_class_data.len() + 8);
+        class_data.extend_from_slice(&[0xCA, 0xFE, 0xBA, 0xBE, 0x00, 0x00, 0x00, 0x2F]);
+        class_data.extend_from_slice(&raw_class_data);
+        decrypt_data(&mut class_data[8..], class_hash, 14, 0);
+        class_data
     }
 }
 
@@ -391,20 +362,356 @@ pub fn decompress_sfs(sfs_file: &SfsFile) -> Result<Vec<u8>> {
     Ok(decompressed)
 }
 
-pub fn unpack_sfs(path: &Path) -> Result<()> {
-    let sfs_file = read_sfs(&path)?;
+/// The key used to decrypt an entry that is encrypted with `decrypt_data`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryKey {
+    pub hash: i32,
+    pub len_offset: i32,
+    pub idx_offset: i32,
+}
+
+impl EntryKey {
+    pub fn decrypt(&self, buf: &mut [u8]) {
+        decrypt_data(buf, self.hash, self.len_offset, self.idx_offset);
+    }
+}
+
+/// The original path of an SFS entry and the key needed to decrypt it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryName {
+    pub file_name: String,
+    pub key: Option<EntryKey>,
+}
+
+/// Recovers the original names of SFS entries from their fingerprints,
+/// e.g. using a dictionary loaded from the SFS database
+pub enum NameResolver<'a> {
+    Dictionary(&'a HashMap<i64, EntryName>),
+    Callback(Box<dyn Fn(i64) -> Option<EntryName> + Sync + 'a>),
+}
+
+impl NameResolver<'_> {
+    pub fn resolve(&self, fingerprint: i64) -> Option<EntryName> {
+        match self {
+            NameResolver::Dictionary(names) => names.get(&fingerprint).cloned(),
+            NameResolver::Callback(resolve) => resolve(fingerprint),
+        }
+    }
+}
+
+/// The errors encountered while unpacking the entries of an SFS file
+#[derive(Debug)]
+pub struct UnpackErrors {
+    pub entry_count: usize,
+    pub errors: Vec<anyhow::Error>,
+}
+
+impl fmt::Display for UnpackErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Unable to unpack {} of {} entries",
+            self.errors.len(),
+            self.entry_count
+        )?;
+
+        for error in self.errors.iter() {
+            writeln!(f, "  {:#}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnpackErrors {}
+
+/// Whether an SFS entry is a directory. The TOC has no flag for directories, and the
+/// names in the SFS database have no trailing separator, so an empty entry whose name
+/// has no extension is taken to be one
+pub fn is_directory_entry(file_name: &str, size: u32) -> bool {
+    size == 0 && !file_name.contains('.')
+}
+
+/// Convert the name of an SFS entry into a path below the output directory,
+/// refusing names that would escape it
+fn entry_output_path(output_dir: &Path, file_name: &str) -> Result<PathBuf> {
+    let relative_path = PathBuf::from(file_name.replace("\\", "/"));
+
+    let is_relative = relative_path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+
+    if is_relative {
+        Ok(output_dir.join(relative_path))
+    } else {
+        bail!("The entry name {} is not a relative path", file_name);
+    }
+}
+
+fn unpack_entry(
+    output_dir: &Path,
+    decompressed: &[u8],
+    entry: &SfsTocItem,
+    entry_name: Option<EntryName>,
+) -> Result<()> {
+    let raw_data = entry_data(decompressed, entry);
+
+    let (output_path, data) = match entry_name {
+        Some(EntryName { file_name, .. }) if is_directory_entry(&file_name, entry.size) => {
+            let dir_path = entry_output_path(output_dir, &file_name)?;
+            return fs::create_dir_all(&dir_path)
+                .with_context(|| format!("Unable to create directory {}", dir_path.display()));
+        }
+        Some(EntryName { file_name, .. }) if file_name.ends_with(".class") => {
+            let class_name = file_name
+                .strip_suffix(".class")
+                .unwrap_or(&file_name)
+                .replace("/", ".");
+            let class_data = decrypt_class(class_hash(&class_name), raw_data.to_vec());
+            (entry_output_path(output_dir, &file_name)?, class_data)
+        }
+        Some(EntryName {
+            file_name,
+            key: Some(key),
+        }) => {
+            let mut data = raw_data.to_vec();
+            key.decrypt(&mut data);
+            (entry_output_path(output_dir, &file_name)?, data)
+        }
+        Some(EntryName {
+            file_name,
+            key: None,
+        }) => (
+            entry_output_path(output_dir, &file_name)?,
+            raw_data.to_vec(),
+        ),
+        None if entry.size == 0 => return Ok(()),
+        None => {
+            let content_type = detect::detect(raw_data);
+            let file_name = format!("{:X}.{}", entry.fingerprint, content_type.extension());
+            (output_dir.join(file_name), raw_data.to_vec())
+        }
+    };
+
+    if let Some(parent_dir) = output_path.parent() {
+        fs::create_dir_all(parent_dir)
+            .with_context(|| format!("Unable to create directory {}", parent_dir.display()))?;
+    }
+
+    fs::write(&output_path, data)
+        .with_context(|| format!("Unable to write file {}", output_path.display()))
+}
+
+/// Unpack every entry of an SFS file into `output_dir`.
+///
+/// Entries that can be named by `resolver` are written to their original
+/// paths and decrypted, while the rest are named after their fingerprint.
+pub fn unpack_sfs(path: &Path, output_dir: &Path, resolver: Option<&NameResolver>) -> Result<()> {
+    let sfs_file = read_sfs(path)?;
     let decompressed = decompress_sfs(&sfs_file)?;
 
-    sfs_file.toc.par_iter().for_each(|entry| {
-        if entry.size > 0 {
-            let start_offset = entry.offset as usize;
-            let end_offset = (entry.offset + entry.size) as usize;
-            let mut data = &decompressed[start_offset..end_offset];
-            let file_name = format!("{:X}.DAT", entry.fingerprint);
-            let mut file = File::create(file_name).unwrap();
-            file.write_all(&mut data).unwrap();
+    fs::create_dir_all(output_dir)
+        .with_context(|| format!("Unable to create directory {}", output_dir.display()))?;
+
+    let errors: Vec<anyhow::Error> = sfs_file
+        .toc
+        .par_iter()
+        .filter_map(|entry| {
+            let entry_name = resolver.and_then(|resolver| resolver.resolve(entry.fingerprint));
+            unpack_entry(output_dir, &decompressed, entry, entry_name)
+                .with_context(|| format!("Unable to unpack entry {:X}", entry.fingerprint))
+                .err()
+        })
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(UnpackErrors {
+            entry_count: sfs_file.toc.len(),
+            errors,
+        })
+        .with_context(|| format!("Unable to unpack SFS file {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+
+    const DIRECTORY: i64 = 1;
+    const PROPERTIES: i64 = 2;
+    const EMPTY_FILE: i64 = 3;
+    const UNNAMED: i64 = 4;
+    const EMPTY_DIRECTORY: i64 = 5;
+
+    const PROPERTIES_DATA: &[u8] = b"name=Bf-109F-4\n";
+    const UNNAMED_DATA: &[u8] = b"Hello, world\n";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sfs-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Write an SFS file holding `entries` in a single deflated chunk
+    fn write_sfs(path: &Path, entries: &[(i64, &[u8])]) {
+        let mut data = Vec::new();
+        let mut toc = Vec::new();
+
+        for (index, (fingerprint, entry_data)) in entries.iter().enumerate() {
+            toc.extend_from_slice(&fingerprint.to_le_bytes());
+            toc.extend_from_slice(&(index as u32).to_le_bytes());
+            toc.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            toc.extend_from_slice(&(entry_data.len() as u32).to_le_bytes());
+            toc.extend_from_slice(&[0u8; 12]);
+            data.extend_from_slice(entry_data);
+        }
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).unwrap();
+        let mut chunk = vec![0, 8, 0];
+        chunk.extend(encoder.finish().unwrap());
+
+        let toc_end = 256 + toc.len() as u32;
+        let chunk_table_end = toc_end + 8;
+
+        let mut header = Vec::new();
+
+        for field in [
+            u32::from_le_bytes(*b"SFS\0"),
+            0x10,
+            0,
+            entries.len() as u32,
+            256,
+            toc_end,
+            chunk_table_end,
+            data.len() as u32,
+        ]
+        .iter()
+        {
+            header.extend_from_slice(&field.to_le_bytes());
         }
-    });
 
-    return Ok(());
+        header.resize(256, 0);
+
+        let checksum: u32 = header[0..8]
+            .iter()
+            .chain(&header[12..])
+            .map(|b| *b as u32)
+            .sum();
+        header[8..12].copy_from_slice(&checksum.to_le_bytes());
+
+        let header_hash = finger::bytes(0, &header);
+
+        let mut chunk_table = Vec::new();
+        chunk_table.extend_from_slice(&chunk_table_end.to_le_bytes());
+        chunk_table.extend_from_slice(&(chunk_table_end + chunk.len() as u32).to_le_bytes());
+
+        sfs_decrypt(header_hash, &mut toc);
+        sfs_decrypt2(header_hash, &mut chunk_table);
+
+        let mut sfs = header;
+        sfs.extend(toc);
+        sfs.extend(chunk_table);
+        sfs.extend(chunk);
+
+        fs::write(path, sfs).unwrap();
+    }
+
+    fn entry_names() -> HashMap<i64, EntryName> {
+        [
+            (DIRECTORY, "i18n"),
+            (PROPERTIES, "i18n/plane.properties"),
+            (EMPTY_FILE, "empty.txt"),
+            (EMPTY_DIRECTORY, "maps"),
+        ]
+        .iter()
+        .map(|(fingerprint, file_name)| {
+            let name = EntryName {
+                file_name: file_name.to_string(),
+                key: None,
+            };
+            (*fingerprint, name)
+        })
+        .collect()
+    }
+
+    fn unpack_test_sfs(name: &str, resolver: &NameResolver) -> PathBuf {
+        let dir = temp_dir(name);
+        let sfs_path = dir.join("test.sfs");
+        let output_dir = dir.join("out");
+
+        write_sfs(
+            &sfs_path,
+            &[
+                (DIRECTORY, b""),
+                (PROPERTIES, PROPERTIES_DATA),
+                (EMPTY_FILE, b""),
+                (UNNAMED, UNNAMED_DATA),
+                (EMPTY_DIRECTORY, b""),
+            ],
+        );
+
+        unpack_sfs(&sfs_path, &output_dir, Some(resolver)).unwrap();
+
+        output_dir
+    }
+
+    fn assert_unpacked(output_dir: &Path) {
+        assert!(output_dir.join("i18n").is_dir());
+        assert_eq!(
+            fs::read(output_dir.join("i18n/plane.properties")).unwrap(),
+            PROPERTIES_DATA
+        );
+        assert!(output_dir.join("maps").is_dir());
+        assert!(output_dir.join("empty.txt").is_file());
+        assert_eq!(fs::read(output_dir.join("4.txt")).unwrap(), UNNAMED_DATA);
+    }
+
+    #[test]
+    fn unpacks_with_dictionary() {
+        let names = entry_names();
+        let output_dir = unpack_test_sfs("dictionary", &NameResolver::Dictionary(&names));
+
+        assert_unpacked(&output_dir);
+        fs::remove_dir_all(output_dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn unpacks_with_callback() {
+        let names = entry_names();
+        let resolver = NameResolver::Callback(Box::new(move |fingerprint| {
+            names.get(&fingerprint).cloned()
+        }));
+        let output_dir = unpack_test_sfs("callback", &resolver);
+
+        assert_unpacked(&output_dir);
+        fs::remove_dir_all(output_dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn directories_are_empty_entries_without_an_extension() {
+        assert!(is_directory_entry("i18n", 0));
+        assert!(is_directory_entry("3DO/PLANE", 0));
+        assert!(!is_directory_entry("empty.txt", 0));
+        assert!(!is_directory_entry("LICENSE", 12));
+    }
+
+    #[test]
+    fn entry_output_path_stays_in_output_dir() {
+        let output_dir = Path::new("out");
+
+        assert_eq!(
+            entry_output_path(output_dir, "i18n\\plane.properties").unwrap(),
+            output_dir.join("i18n").join("plane.properties")
+        );
+        assert!(entry_output_path(output_dir, "../plane.properties").is_err());
+        assert!(entry_output_path(output_dir, "i18n/../../plane.properties").is_err());
+        assert!(entry_output_path(output_dir, "/etc/passwd").is_err());
+        assert!(entry_output_path(output_dir, "\\Windows\\win.ini").is_err());
+    }
 }